@@ -0,0 +1,72 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use walrus_core::utils;
+use walrus_core::utils::Dirs;
+
+// How often to check for new lines when following the log.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Prints the last `lines` lines of the daemon's current log file, then keeps printing whatever
+/// is added to it if `follow` is set.
+pub fn print(lines: usize, follow: bool) -> io::Result<()> {
+    let dir = utils::get_dir(Dirs::State)
+        .map_err(io::Error::other)?
+        .join(utils::APPNAME)
+        .join(utils::LOG_DIR);
+    let Some(mut path) = latest(&dir)? else {
+        println!("No logs in {}", dir.display());
+        return Ok(());
+    };
+
+    let mut file = File::open(&path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let start = contents.lines().count().saturating_sub(lines);
+    let mut stdout = io::stdout();
+    for line in contents.lines().skip(start) {
+        writeln!(stdout, "{line}")?;
+    }
+
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+        io::copy(&mut file, &mut stdout)?;
+        stdout.flush()?;
+
+        // The daemon starts a new file every day.
+        if let Some(newest) = latest(&dir)?
+            && newest != path
+        {
+            file = File::open(&newest)?;
+            path = newest;
+        }
+    }
+}
+
+// The most recently written log file.
+fn latest(dir: &Path) -> io::Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    Ok(fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(utils::APPNAME))
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max()
+        .map(|(_, path)| path))
+}
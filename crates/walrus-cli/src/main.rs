@@ -7,6 +7,8 @@ use walrus_core::commands::Commands;
 use walrus_core::config::Config;
 use walrus_core::ipc;
 
+mod logs;
+
 fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -20,13 +22,31 @@ fn main() {
     });
 
     let cli = Cli::parse();
+    if let Some(socket) = cli.socket.clone() {
+        ipc::set_socket_path(socket);
+    }
     if let Some(cmd) = &cli.command {
         match cmd {
+            Commands::Logs { follow, lines } => {
+                if let Err(e) = logs::print(*lines, *follow) {
+                    tracing::error!("Error reading logs: {e}");
+                }
+            }
             Commands::Config => {
                 tracing::debug!("Printing config to stdout...");
                 tracing::debug!("{config}");
                 println!("{config}");
             }
+            ipc_cmd if ipc_cmd.expects_reply() => {
+                tracing::debug!("Attempting to request {ipc_cmd:?} via IPC...");
+                match ipc::send_request(ipc_cmd.clone()) {
+                    Ok(reply) => println!("{reply}"),
+                    Err(e) => {
+                        tracing::error!("Error sending command to walrus-daemon instance: {e}");
+                        tracing::error!("Is walrus-daemon running?");
+                    }
+                }
+            }
             ipc_cmd => {
                 tracing::debug!("Attempting to send {ipc_cmd:?} command via IPC...");
                 ipc::send_command(ipc_cmd.clone()).unwrap_or_else(|e| {
@@ -1,14 +1,27 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use bincode::Decode;
 use bincode::Encode;
 use bincode::config;
 use clap::Parser;
 use clap::Subcommand;
 
+use crate::utils;
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Clone, Parser)]
 #[command(name = "Walrus", version = VERSION, about = "Convenient wrapper for swww with sensible defaults", long_about = None)]
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        env = "WALRUS_SOCKET",
+        help = "IPC socket of the walrus-daemon instance to talk to"
+    )]
+    pub socket: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -17,22 +30,112 @@ pub struct Cli {
 pub enum Commands {
     #[command(about = "Categorise current wallpaper")]
     Categorise { category: String },
+    #[command(about = "Manage categories")]
+    Category {
+        #[command(subcommand)]
+        action: CategoryAction,
+    },
     #[command(about = "Prints config")]
     Config,
+    #[command(about = "Find duplicate wallpapers")]
+    Dedupe,
+    #[command(about = "Dislike current wallpaper and remove it from the rotation")]
+    Dislike,
+    #[command(about = "Like current wallpaper")]
+    Like,
+    #[command(about = "Print the end of the daemon's log")]
+    Logs {
+        #[arg(short, long, help = "Keep printing new lines as they're logged")]
+        follow: bool,
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 50,
+            help = "How many lines to print"
+        )]
+        lines: usize,
+    },
     #[command(about = "Go to the next wallpaper in queue")]
     Next,
     #[command(about = "Pause the playback")]
-    Pause,
+    Pause {
+        #[arg(
+            long = "for",
+            value_name = "DURATION",
+            value_parser = utils::parse_duration,
+            conflicts_with = "until",
+            help = "Resume after this long, e.g. 30m or 1h30m"
+        )]
+        duration: Option<Duration>,
+        // Sent as the time left until then, so the daemon doesn't need the local time zone.
+        #[arg(
+            long,
+            value_name = "HH:MM",
+            value_parser = utils::parse_time_until,
+            help = "Resume at this time of day, e.g. 18:00"
+        )]
+        until: Option<Duration>,
+    },
     #[command(about = "Go to the previous wallpaper in queue")]
     Previous,
     #[command(about = "Resume the playback")]
     Resume,
     #[command(about = "Stops the program")]
     Shutdown,
+    #[command(about = "Manage tags")]
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    #[command(about = "Pause the playback if it's playing, resume it otherwise")]
+    Toggle,
     #[command(hide = true)]
     Reload,
 }
 
+#[derive(Clone, Debug, Decode, Encode, Subcommand)]
+pub enum CategoryAction {
+    #[command(about = "List all categories")]
+    List,
+    #[command(about = "Show which categories the current wallpaper is in")]
+    Show,
+    #[command(about = "Remove current wallpaper from a category")]
+    Remove { category: String },
+    #[command(about = "Only play wallpapers from a category")]
+    Play { category: String },
+    #[command(about = "Play wallpapers from all categories again")]
+    PlayAll,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Subcommand)]
+pub enum TagAction {
+    #[command(about = "Tag current wallpaper")]
+    Add {
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    #[command(about = "Remove tags from current wallpaper")]
+    Remove {
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    #[command(about = "Show the tags of current wallpaper")]
+    Show,
+    #[command(about = "List all tags")]
+    List,
+    #[command(
+        about = "Only play wallpapers matching a query, e.g. tag:dark AND NOT tag:busy",
+        long_about = "Only play wallpapers matching a query, e.g. tag:dark AND NOT \
+                      tag:busy\n\nUse `walrus category play-all` to play all wallpapers again."
+    )]
+    Play {
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    #[command(about = "Tag categorised wallpapers with the names of their categories")]
+    Import,
+}
+
 impl Commands {
     pub fn to_bytes(&self) -> Option<Vec<u8>> {
         bincode::encode_to_vec(self, config::standard()).ok()
@@ -49,4 +152,12 @@ impl Commands {
             _ => Some(decoded),
         }
     }
+
+    /// Whether the daemon answers this command with a reply the client should wait for.
+    pub fn expects_reply(&self) -> bool {
+        matches!(
+            self,
+            Commands::Category { .. } | Commands::Dedupe | Commands::Tag { .. }
+        )
+    }
 }
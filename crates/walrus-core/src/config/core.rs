@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 use std::fs;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -18,22 +20,43 @@ use serde::Serialize;
 
 use super::HighestRefreshRate;
 use super::HighestResolution;
+use super::MonitorInfo;
 use super::Resolution;
 use super::TransitionFlavour;
 use super::defaults::*;
 use crate::commands::Commands;
 use crate::config::Bezier;
 use crate::config::FilterMethod;
+use crate::config::Hook;
+use crate::config::Interval;
+use crate::config::Order;
 use crate::config::ResizeMethod;
+use crate::ipc::Request;
 use crate::utils;
 use crate::utils::DirError;
 use crate::utils::Dirs;
 use crate::wayland::WaylandHandle;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Config {
+    pub(super) dedupe: Option<Dedupe>,
     pub(super) general: Option<General>,
+    pub(super) hooks: Option<Hooks>,
+    // Directories, relative to wallpaper_path, whose wallpapers stay for a different interval.
+    pub(super) intervals: Option<BTreeMap<PathBuf, Interval>>,
+    pub(super) logs: Option<Logs>,
+    // Outputs found when the config was loaded.
+    #[serde(skip)]
+    pub(super) outputs: Vec<MonitorInfo>,
+    // Whether the system runs on battery, which makes [power.battery] apply. Set by the daemon.
+    #[serde(skip)]
+    pub(super) on_battery: bool,
+    pub(super) power: Option<Power>,
+    pub(super) prescale: Option<Prescale>,
+    pub(super) swww: Option<Swww>,
+    pub(super) tags: Option<Tags>,
+    pub(super) theme: Option<Theme>,
     pub(super) transition: Option<Transition>,
 }
 
@@ -49,7 +72,11 @@ impl Config {
                 DirError::DoesNotExist(_) => unreachable!(),
             },
         };
-        let config_raw = fs::read_to_string(&path)?;
+        Self::from_file(&path)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config_raw = fs::read_to_string(path)?;
         Ok(Self::from_raw(&config_raw))
     }
 
@@ -61,22 +88,25 @@ impl Config {
         });
 
         let (fps, res) = match WaylandHandle::new() {
-            Ok(mut wayland) => wayland
-                .get_outputs()
-                .iter()
-                .max_by(|a, b| {
-                    HighestRefreshRate(a)
-                        .cmp(&HighestRefreshRate(b))
-                        .then_with(|| HighestResolution(a).cmp(&HighestResolution(b)))
-                })
-                .map_or_else(
-                    || {
-                        tracing::error!("No monitors found");
-                        tracing::warn!("Falling back to default FPS and resolution");
-                        (FALLBACK_FPS, FALLBACK_RESOLUTION)
-                    },
-                    |m| (m.refresh_rate.round() as u32, m.resolution),
-                ),
+            Ok(mut wayland) => {
+                config.outputs = wayland.get_outputs().to_vec();
+                config
+                    .outputs
+                    .iter()
+                    .max_by(|a, b| {
+                        HighestRefreshRate(a)
+                            .cmp(&HighestRefreshRate(b))
+                            .then_with(|| HighestResolution(a).cmp(&HighestResolution(b)))
+                    })
+                    .map_or_else(
+                        || {
+                            tracing::error!("No monitors found");
+                            tracing::warn!("Falling back to default FPS and resolution");
+                            (FALLBACK_FPS, FALLBACK_RESOLUTION)
+                        },
+                        |m| (m.refresh_rate.round() as u32, m.resolution),
+                    )
+            }
             Err(e) => {
                 tracing::warn!("Failed to connect to Wayland: {e}");
                 (FALLBACK_FPS, FALLBACK_RESOLUTION)
@@ -104,7 +134,7 @@ impl Config {
         config
     }
 
-    pub fn watch<P: AsRef<Path>>(path: P, cmd_tx: Sender<Commands>) -> notify::Result<()> {
+    pub fn watch<P: AsRef<Path>>(path: P, cmd_tx: Sender<Request>) -> notify::Result<()> {
         tracing::debug!("Starting watcher...");
         let (tx, rx) = mpsc::channel();
         let mut watcher = RecommendedWatcher::new(
@@ -125,7 +155,7 @@ impl Config {
                 let event = event_res?;
                 tracing::debug!("File event: {event:?}");
                 if event.kind.is_modify() || event.kind.is_remove() {
-                    cmd_tx.send(Commands::Reload).unwrap();
+                    cmd_tx.send(Commands::Reload.into()).unwrap();
 
                     if event.kind.is_remove() {
                         tracing::debug!("File removed, trying to re-establish watch");
@@ -143,18 +173,79 @@ impl Config {
 }
 
 impl Config {
+    fn dedupe_section(&self) -> Dedupe {
+        self.dedupe.clone().unwrap_or_default()
+    }
+
     fn general(&self) -> General {
         self.general.clone().unwrap_or_default()
     }
 
+    fn hooks(&self) -> Hooks {
+        self.hooks.clone().unwrap_or_default()
+    }
+
+    fn logs(&self) -> Logs {
+        self.logs.clone().unwrap_or_default()
+    }
+
+    fn power(&self) -> Power {
+        self.power.clone().unwrap_or_default()
+    }
+
+    // The battery overrides, while on battery.
+    fn battery(&self) -> Battery {
+        if self.on_battery {
+            self.power().battery.unwrap_or_default()
+        } else {
+            Battery::default()
+        }
+    }
+
+    fn prescale_section(&self) -> Prescale {
+        self.prescale.clone().unwrap_or_default()
+    }
+
+    fn swww(&self) -> Swww {
+        self.swww.clone().unwrap_or_default()
+    }
+
+    fn tags(&self) -> Tags {
+        self.tags.clone().unwrap_or_default()
+    }
+
+    fn theme(&self) -> Theme {
+        self.theme.clone().unwrap_or_default()
+    }
+
     fn transition(&self) -> Transition {
         self.transition.clone().unwrap_or_default()
     }
 
+    pub fn align(&self) -> bool {
+        self.general().align()
+    }
+
     pub fn bezier(&self) -> [f32; 4] {
         self.transition().bezier()
     }
 
+    pub fn change_after_suspend(&self) -> bool {
+        self.general().change_after_suspend()
+    }
+
+    pub fn debug(&self) -> String {
+        self.general().debug()
+    }
+
+    pub fn dedupe(&self) -> bool {
+        self.dedupe_section().enabled()
+    }
+
+    pub fn dedupe_threshold(&self) -> u32 {
+        self.dedupe_section().threshold()
+    }
+
     pub fn duration(&self) -> f64 {
         self.transition().duration()
     }
@@ -171,16 +262,126 @@ impl Config {
         self.transition().filter()
     }
 
+    pub fn battery_pause_below(&self) -> u8 {
+        self.power().battery.unwrap_or_default().pause_below()
+    }
+
     pub fn flavour(&self) -> Vec<TransitionFlavour> {
-        self.transition().flavour()
+        self.battery()
+            .flavour
+            .unwrap_or_else(|| self.transition().flavour())
     }
 
     pub fn fps(&self) -> u32 {
-        self.transition().fps()
+        self.battery()
+            .fps
+            .unwrap_or_else(|| self.transition().fps())
+    }
+
+    pub fn exclude(&self) -> Vec<String> {
+        self.general().exclude()
+    }
+
+    pub fn hook(&self, hook: Hook) -> Option<String> {
+        self.hooks().command(hook)
+    }
+
+    pub fn hook_timeout(&self) -> u64 {
+        self.hooks().timeout()
+    }
+
+    pub fn ignored_dirs(&self) -> Vec<PathBuf> {
+        self.general().ignored_dirs()
+    }
+
+    pub fn include(&self) -> Vec<String> {
+        self.general().include()
+    }
+
+    pub fn interval(&self) -> Interval {
+        self.battery()
+            .interval
+            .unwrap_or_else(|| self.general().interval())
+    }
+
+    /// The interval for `wallpaper`, from the most specific directory in `[intervals]` it's in.
+    /// Category directories like `.animated` hold symlinks rather than the wallpapers themselves,
+    /// so they apply to the wallpapers in `categories` instead. The battery interval takes
+    /// precedence while on battery.
+    pub fn interval_for(&self, wallpaper: &Path, categories: &[String]) -> Interval {
+        if let Some(interval) = self.battery().interval {
+            return interval;
+        }
+        let base = self.wallpaper_path();
+        let rel = wallpaper.strip_prefix(&base).ok();
+        // E.g. `.animated/short` for wallpapers under `short` in the `animated` category.
+        let in_category = |dir: &Path| {
+            let mut components = dir.components();
+            let Some(Component::Normal(name)) = components.next() else {
+                return false;
+            };
+            name.to_str()
+                .and_then(|name| name.strip_prefix('.'))
+                .is_some_and(|name| categories.iter().any(|c| c == name))
+                && rel.is_some_and(|rel| rel.starts_with(components.as_path()))
+        };
+
+        self.intervals
+            .iter()
+            .flatten()
+            .filter(|(dir, _)| wallpaper.starts_with(base.join(dir)) || in_category(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map_or_else(|| self.interval(), |(_, interval)| *interval)
+    }
+
+    pub fn like_weight(&self) -> u32 {
+        self.general().like_weight()
+    }
+
+    pub fn log_json(&self) -> bool {
+        self.logs().json()
+    }
+
+    pub fn log_max_age(&self) -> u64 {
+        self.logs().max_age()
+    }
+
+    pub fn log_max_files(&self) -> usize {
+        self.logs().max_files()
+    }
+
+    pub fn match_aspect(&self) -> bool {
+        self.general().match_aspect()
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.general().max_depth
+    }
+
+    pub fn min_resolution_ratio(&self) -> f64 {
+        self.general().min_resolution_ratio()
+    }
+
+    pub fn outputs(&self) -> &[MonitorInfo] {
+        &self.outputs
+    }
+
+    pub fn power_sysfs(&self) -> PathBuf {
+        self.power().sysfs()
+    }
+
+    /// Switches to or from the `[power.battery]` settings.
+    pub fn set_on_battery(&mut self, on_battery: bool) {
+        self.on_battery = on_battery;
+    }
+
+    pub fn prescale(&self) -> bool {
+        self.prescale_section().enabled()
     }
 
-    pub fn interval(&self) -> u64 {
-        self.general().interval()
+    /// In MiB.
+    pub fn prescale_max_size(&self) -> u64 {
+        self.prescale_section().max_size()
     }
 
     pub fn resize(&self) -> ResizeMethod {
@@ -191,10 +392,18 @@ impl Config {
         self.general().resolution()
     }
 
+    pub fn order(&self) -> Order {
+        self.general().order()
+    }
+
     pub fn shuffle(&self) -> bool {
         self.general().shuffle()
     }
 
+    pub fn span(&self) -> bool {
+        self.general().span()
+    }
+
     pub fn step(&self) -> u8 {
         self.transition().step()
     }
@@ -203,6 +412,38 @@ impl Config {
         self.general().swww_path()
     }
 
+    pub fn swww_daemon(&self) -> Option<String> {
+        self.swww().daemon
+    }
+
+    pub fn swww_retries(&self) -> u32 {
+        self.swww().retries()
+    }
+
+    pub fn swww_timeout(&self) -> u64 {
+        self.swww().timeout()
+    }
+
+    pub fn tags_enabled(&self) -> bool {
+        self.tags().enabled()
+    }
+
+    pub fn tags_path(&self) -> Option<PathBuf> {
+        self.tags().path()
+    }
+
+    pub fn theme_enabled(&self) -> bool {
+        self.theme().enabled()
+    }
+
+    pub fn theme_reload(&self) -> Option<String> {
+        self.theme().reload
+    }
+
+    pub fn theme_templates(&self) -> Option<PathBuf> {
+        self.theme().templates()
+    }
+
     pub fn wallpaper_path(&self) -> PathBuf {
         self.general().wallpaper_path()
     }
@@ -221,29 +462,120 @@ impl Display for Config {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Dedupe {
+    pub(super) enabled: Option<bool>,
+    pub(super) threshold: Option<u32>,
+}
+
+impl Dedupe {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(DEFAULT_DEDUPE)
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold.unwrap_or(DEFAULT_DEDUPE_THRESHOLD)
+    }
+}
+
+impl Default for Dedupe {
+    fn default() -> Self {
+        Dedupe {
+            enabled: Some(DEFAULT_DEDUPE),
+            threshold: Some(DEFAULT_DEDUPE_THRESHOLD),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub(super) struct General {
-    pub(super) interval: Option<u64>,
+    pub(super) align: Option<bool>,
+    pub(super) change_after_suspend: Option<bool>,
+    pub(super) debug: Option<String>,
+    pub(super) exclude: Option<Vec<String>>,
+    pub(super) ignored_dirs: Option<Vec<PathBuf>>,
+    pub(super) include: Option<Vec<String>>,
+    pub(super) interval: Option<Interval>,
+    pub(super) like_weight: Option<u32>,
+    pub(super) match_aspect: Option<bool>,
+    pub(super) max_depth: Option<usize>,
+    pub(super) min_resolution_ratio: Option<f64>,
+    #[serde(deserialize_with = "deserialize_order")]
+    pub(super) order: Option<Order>,
     pub(super) resolution: Option<Resolution>,
     pub(super) shuffle: Option<bool>,
+    pub(super) span: Option<bool>,
     pub(super) swww_path: Option<String>,
     pub(super) wallpaper_path: Option<PathBuf>,
 }
 
 impl General {
-    pub fn interval(&self) -> u64 {
-        self.interval.unwrap_or(DEFAULT_INTERVAL)
+    pub fn align(&self) -> bool {
+        self.align.unwrap_or(DEFAULT_ALIGN)
+    }
+
+    pub fn change_after_suspend(&self) -> bool {
+        self.change_after_suspend
+            .unwrap_or(DEFAULT_CHANGE_AFTER_SUSPEND)
+    }
+
+    pub fn debug(&self) -> String {
+        self.debug.as_deref().unwrap_or(DEFAULT_DEBUG).into()
+    }
+
+    pub fn exclude(&self) -> Vec<String> {
+        self.exclude.clone().unwrap_or_default()
+    }
+
+    pub fn ignored_dirs(&self) -> Vec<PathBuf> {
+        self.ignored_dirs.clone().unwrap_or_default()
+    }
+
+    pub fn include(&self) -> Vec<String> {
+        self.include.clone().unwrap_or_default()
+    }
+
+    pub fn interval(&self) -> Interval {
+        self.interval
+            .unwrap_or(Interval::fixed(Duration::from_secs(DEFAULT_INTERVAL)))
+    }
+
+    pub fn like_weight(&self) -> u32 {
+        self.like_weight.unwrap_or(DEFAULT_LIKE_WEIGHT)
+    }
+
+    pub fn match_aspect(&self) -> bool {
+        self.match_aspect.unwrap_or(DEFAULT_MATCH_ASPECT)
+    }
+
+    pub fn min_resolution_ratio(&self) -> f64 {
+        self.min_resolution_ratio
+            .unwrap_or(DEFAULT_MIN_RESOLUTION_RATIO)
     }
 
     pub fn resolution(&self) -> Resolution {
         self.resolution.unwrap_or(FALLBACK_RESOLUTION)
     }
 
+    // Falls back to `shuffle`, which predates `order`.
+    pub fn order(&self) -> Order {
+        self.order.unwrap_or(if self.shuffle() {
+            Order::Shuffle
+        } else {
+            Order::Sorted
+        })
+    }
+
     pub fn shuffle(&self) -> bool {
         self.shuffle.unwrap_or(DEFAULT_SHUFFLE)
     }
 
+    pub fn span(&self) -> bool {
+        self.span.unwrap_or(DEFAULT_SPAN)
+    }
+
     pub fn swww_path(&self) -> String {
         self.swww_path.as_deref().unwrap_or(DEFAULT_SWW_PATH).into()
     }
@@ -260,15 +592,256 @@ impl Default for General {
             .join(DEFAULT_WALLPAPER_DIR);
 
         General {
-            interval: Some(DEFAULT_INTERVAL),
+            align: Some(DEFAULT_ALIGN),
+            change_after_suspend: Some(DEFAULT_CHANGE_AFTER_SUSPEND),
+            debug: Some(DEFAULT_DEBUG.into()),
+            exclude: Some(Vec::new()),
+            ignored_dirs: Some(Vec::new()),
+            include: Some(Vec::new()),
+            interval: Some(Interval::fixed(Duration::from_secs(DEFAULT_INTERVAL))),
+            like_weight: Some(DEFAULT_LIKE_WEIGHT),
+            match_aspect: Some(DEFAULT_MATCH_ASPECT),
+            max_depth: None,
+            min_resolution_ratio: Some(DEFAULT_MIN_RESOLUTION_RATIO),
             resolution: None,
+            order: None,
             shuffle: Some(DEFAULT_SHUFFLE),
+            span: Some(DEFAULT_SPAN),
             swww_path: Some(DEFAULT_SWW_PATH.into()),
             wallpaper_path: Some(wallpaper_path),
         }
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Hooks {
+    pub(super) on_pause: Option<String>,
+    pub(super) on_reload: Option<String>,
+    pub(super) on_resume: Option<String>,
+    pub(super) post_change: Option<String>,
+    pub(super) pre_change: Option<String>,
+    pub(super) timeout: Option<u64>,
+}
+
+impl Hooks {
+    pub fn command(&self, hook: Hook) -> Option<String> {
+        match hook {
+            Hook::PreChange => self.pre_change.clone(),
+            Hook::PostChange => self.post_change.clone(),
+            Hook::Pause => self.on_pause.clone(),
+            Hook::Resume => self.on_resume.clone(),
+            Hook::Reload => self.on_reload.clone(),
+        }
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT)
+    }
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            on_pause: None,
+            on_reload: None,
+            on_resume: None,
+            post_change: None,
+            pre_change: None,
+            timeout: Some(DEFAULT_HOOK_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Power {
+    pub(super) battery: Option<Battery>,
+    // Where sysfs is mounted, to read /sys/class/power_supply from.
+    pub(super) sysfs: Option<PathBuf>,
+}
+
+impl Power {
+    pub fn sysfs(&self) -> PathBuf {
+        self.sysfs.clone().unwrap_or_else(|| DEFAULT_SYSFS.into())
+    }
+}
+
+impl Default for Power {
+    fn default() -> Self {
+        Power {
+            battery: None,
+            sysfs: Some(DEFAULT_SYSFS.into()),
+        }
+    }
+}
+
+// Used instead of the usual settings while on battery, those apply to anything left out.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Battery {
+    #[serde(deserialize_with = "deserialize_flavour")]
+    pub(super) flavour: Option<Vec<TransitionFlavour>>,
+    pub(super) fps: Option<u32>,
+    pub(super) interval: Option<Interval>,
+    // Percentage below which to pause, 0 to never pause.
+    pub(super) pause_below: Option<u8>,
+}
+
+impl Battery {
+    pub fn pause_below(&self) -> u8 {
+        self.pause_below.unwrap_or(DEFAULT_PAUSE_BELOW)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Logs {
+    pub(super) json: Option<bool>,
+    pub(super) max_age: Option<u64>,
+    pub(super) max_files: Option<usize>,
+}
+
+impl Logs {
+    pub fn json(&self) -> bool {
+        self.json.unwrap_or(DEFAULT_LOG_JSON)
+    }
+
+    pub fn max_age(&self) -> u64 {
+        self.max_age.unwrap_or(DEFAULT_LOG_MAX_AGE)
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES)
+    }
+}
+
+impl Default for Logs {
+    fn default() -> Self {
+        Logs {
+            json: Some(DEFAULT_LOG_JSON),
+            max_age: Some(DEFAULT_LOG_MAX_AGE),
+            max_files: Some(DEFAULT_LOG_MAX_FILES),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Prescale {
+    pub(super) enabled: Option<bool>,
+    pub(super) max_size: Option<u64>,
+}
+
+impl Prescale {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(DEFAULT_PRESCALE)
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(DEFAULT_PRESCALE_MAX_SIZE)
+    }
+}
+
+impl Default for Prescale {
+    fn default() -> Self {
+        Prescale {
+            enabled: Some(DEFAULT_PRESCALE),
+            max_size: Some(DEFAULT_PRESCALE_MAX_SIZE),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Swww {
+    pub(super) daemon: Option<String>,
+    pub(super) retries: Option<u32>,
+    pub(super) timeout: Option<u64>,
+}
+
+impl Swww {
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(DEFAULT_SWWW_RETRIES)
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout.unwrap_or(DEFAULT_SWWW_TIMEOUT)
+    }
+}
+
+impl Default for Swww {
+    fn default() -> Self {
+        Swww {
+            daemon: None,
+            retries: Some(DEFAULT_SWWW_RETRIES),
+            timeout: Some(DEFAULT_SWWW_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Tags {
+    pub(super) enabled: Option<bool>,
+    pub(super) path: Option<PathBuf>,
+}
+
+impl Tags {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(DEFAULT_TAGS)
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.clone().or_else(|| {
+            utils::get_dir(Dirs::Data)
+                .ok()
+                .map(|p| p.join(utils::APPNAME).join(DEFAULT_TAGS_FILE))
+        })
+    }
+}
+
+impl Default for Tags {
+    fn default() -> Self {
+        Tags {
+            enabled: Some(DEFAULT_TAGS),
+            path: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(super) struct Theme {
+    pub(super) enabled: Option<bool>,
+    pub(super) reload: Option<String>,
+    pub(super) templates: Option<PathBuf>,
+}
+
+impl Theme {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(DEFAULT_THEME)
+    }
+
+    pub fn templates(&self) -> Option<PathBuf> {
+        self.templates.clone().or_else(|| {
+            utils::get_dir(Dirs::Config)
+                .ok()
+                .map(|p| p.join(utils::APPNAME).join(DEFAULT_TEMPLATES_DIR))
+        })
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            enabled: Some(DEFAULT_THEME),
+            reload: None,
+            templates: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub(super) struct Transition {
@@ -362,6 +935,16 @@ where
     }
 }
 
+fn deserialize_order<'de, D>(d: D) -> Result<Option<Order>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let order: Option<String> = Option::deserialize(d)?;
+    order
+        .map(|order| Order::from_str(&order).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
 fn deserialize_flavour<'de, D>(d: D) -> Result<Option<Vec<TransitionFlavour>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -455,4 +1038,99 @@ mod tests {
 
         // 3. After this fallback values would be used...
     }
+
+    #[test]
+    fn test_interval() {
+        let toml = r#"
+            [general]
+            interval = "5m"
+            wallpaper_path = "/wallpapers"
+
+            [intervals]
+            ".animated" = { min = 600, max = "15m" }
+            ".animated/short" = 90
+            "/elsewhere" = "1h"
+        "#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let secs = |secs| Interval::fixed(Duration::from_secs(secs));
+        let animated = ["animated".to_owned()];
+
+        assert_eq!(config.interval(), secs(300));
+        assert_eq!(
+            config.interval_for(Path::new("/wallpapers/a.gif"), &[]),
+            secs(300)
+        );
+        assert_eq!(
+            config.interval_for(Path::new("/wallpapers/a.gif"), &animated),
+            Interval {
+                min: Duration::from_secs(600),
+                max: Duration::from_secs(900),
+            }
+        );
+        assert_eq!(
+            config.interval_for(Path::new("/wallpapers/short/a.gif"), &animated),
+            secs(90)
+        );
+        assert_eq!(
+            config.interval_for(Path::new("/elsewhere/a.png"), &[]),
+            secs(3600)
+        );
+        // Only whole category names match.
+        assert_eq!(
+            config.interval_for(Path::new("/wallpapers/a.gif"), &["animated2".to_owned()]),
+            secs(300)
+        );
+
+        assert!(toml::from_str::<General>("interval = { min = 60, max = 30 }").is_err());
+        assert!(toml::from_str::<General>(r#"interval = "5 parsecs""#).is_err());
+        // Written back as seconds.
+        let general: General = toml::from_str("interval = \"1h\"").unwrap();
+        assert!(
+            toml::to_string(&general)
+                .unwrap()
+                .contains("interval = 3600")
+        );
+    }
+
+    #[test]
+    fn test_battery() {
+        let toml = r#"
+            [general]
+            interval = "5m"
+            wallpaper_path = "/wallpapers"
+
+            [intervals]
+            ".animated" = "15m"
+
+            [power.battery]
+            flavour = ["none"]
+            interval = "1h"
+            pause_below = 20
+
+            [transition]
+            fps = 144
+        "#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        let animated = Path::new("/wallpapers/a.gif");
+        let categories = ["animated".to_owned()];
+
+        assert_eq!(config.fps(), 144);
+        assert_eq!(config.flavour().len(), DEFAULT_FLAVOUR.len());
+        assert_eq!(
+            config.interval_for(animated, &categories).min,
+            Duration::from_mins(15)
+        );
+
+        config.set_on_battery(true);
+        // Left out, so the usual setting.
+        assert_eq!(config.fps(), 144);
+        assert_eq!(config.flavour()[0].to_string(), "none");
+        assert_eq!(config.interval(), Interval::fixed(Duration::from_hours(1)));
+        assert_eq!(
+            config.interval_for(animated, &categories),
+            config.interval()
+        );
+        assert_eq!(config.battery_pause_below(), 20);
+        assert_eq!(config.power_sysfs(), Path::new("/sys"));
+    }
 }
@@ -3,11 +3,13 @@ use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::str::FromStr;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde::Serialize;
 
 pub use self::core::Config;
+use crate::utils;
 
 mod core;
 mod defaults {
@@ -16,10 +18,22 @@ mod defaults {
     use crate::config::FilterMethod;
     use crate::config::ResizeMethod;
 
+    pub(super) const DEFAULT_ALIGN: bool = false;
     pub(super) const DEFAULT_BEZIER: [f32; 4] = [0.4, 0.0, 0.6, 1.0];
+    pub(super) const DEFAULT_CHANGE_AFTER_SUSPEND: bool = true;
+    pub(super) const DEFAULT_DEBUG: &str = "info";
+    pub(super) const DEFAULT_DEDUPE: bool = false;
+    pub(super) const DEFAULT_DEDUPE_THRESHOLD: u32 = 5;
     pub(super) const DEFAULT_DURATION: f64 = 1.0;
     pub(super) const DEFAULT_DYNAMIC_DURATION: bool = true;
+    pub(super) const DEFAULT_HOOK_TIMEOUT: u64 = 30;
     pub(super) const DEFAULT_INTERVAL: u64 = 300;
+    pub(super) const DEFAULT_LIKE_WEIGHT: u32 = 1;
+    pub(super) const DEFAULT_LOG_JSON: bool = false;
+    pub(super) const DEFAULT_LOG_MAX_AGE: u64 = 30;
+    pub(super) const DEFAULT_LOG_MAX_FILES: usize = 7;
+    pub(super) const DEFAULT_MATCH_ASPECT: bool = false;
+    pub(super) const DEFAULT_MIN_RESOLUTION_RATIO: f64 = 0.0;
     pub(super) const DEFAULT_FILL: &str = "000000";
     pub(super) const DEFAULT_FILTER: FilterMethod = FilterMethod::Lanczos3;
     pub(super) const DEFAULT_FLAVOUR: [TransitionFlavour; 4] = [
@@ -28,10 +42,21 @@ mod defaults {
         TransitionFlavour::Grow,
         TransitionFlavour::Outer,
     ];
+    pub(super) const DEFAULT_PAUSE_BELOW: u8 = 0;
+    pub(super) const DEFAULT_PRESCALE: bool = false;
+    pub(super) const DEFAULT_PRESCALE_MAX_SIZE: u64 = 1024;
     pub(super) const DEFAULT_RESIZE: ResizeMethod = ResizeMethod::No;
     pub(super) const DEFAULT_SHUFFLE: bool = true;
+    pub(super) const DEFAULT_SPAN: bool = false;
     pub(super) const DEFAULT_STEP: u8 = 60;
+    pub(super) const DEFAULT_SYSFS: &str = "/sys";
     pub(super) const DEFAULT_SWW_PATH: &str = "/usr/bin/swww";
+    pub(super) const DEFAULT_SWWW_RETRIES: u32 = 2;
+    pub(super) const DEFAULT_SWWW_TIMEOUT: u64 = 10;
+    pub(super) const DEFAULT_TAGS: bool = false;
+    pub(super) const DEFAULT_TAGS_FILE: &str = "tags.toml";
+    pub(super) const DEFAULT_TEMPLATES_DIR: &str = "templates";
+    pub(super) const DEFAULT_THEME: bool = false;
     pub(super) const DEFAULT_WALLPAPER_DIR: &str = "Wallpapers";
     pub(super) const DEFAULT_WAVE_SIZE: (u32, u32, u32, u32) = (70, 80, 35, 40);
 
@@ -42,11 +67,38 @@ mod defaults {
     };
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MonitorInfo {
     pub refresh_rate: f32,
     pub resolution: Resolution,
     pub id: u32,
+    // Connector name, e.g. DP-1. This is what swww uses to target outputs.
+    pub name: Option<String>,
+    // Whether the output is turned sideways, in which case the resolution is swapped on screen.
+    pub rotated: bool,
+    // Top-left corner and size in the compositor's logical coordinates, which account for scaling
+    // and rotation. This is how outputs are laid out next to each other.
+    pub position: (i32, i32),
+    pub logical_size: (i32, i32),
+}
+
+impl MonitorInfo {
+    pub fn is_portrait(&self) -> bool {
+        (self.resolution.height > self.resolution.width) != self.rotated
+    }
+
+    /// Width and height in pixels as seen on screen, i.e. after rotation.
+    pub fn screen_size(&self) -> (u32, u32) {
+        let (width, height) = (
+            self.resolution.width.unsigned_abs(),
+            self.resolution.height.unsigned_abs(),
+        );
+        if self.rotated {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
@@ -141,6 +193,10 @@ pub enum TransitionFlavour {
     Wave,
     Grow,
     Outer,
+    // A plain crossfade, cheaper than the others.
+    Simple,
+    // Switches at once.
+    None,
 }
 
 impl Display for TransitionFlavour {
@@ -150,6 +206,8 @@ impl Display for TransitionFlavour {
             Self::Wave => "wave",
             Self::Grow => "grow",
             Self::Outer => "outer",
+            Self::Simple => "simple",
+            Self::None => "none",
         })
     }
 }
@@ -163,11 +221,145 @@ impl FromStr for TransitionFlavour {
             "wave" => Ok(Self::Wave),
             "grow" => Ok(Self::Grow),
             "outer" => Ok(Self::Outer),
+            "simple" => Ok(Self::Simple),
+            "none" => Ok(Self::None),
             _ => Err(format!("Invalid transition type: {s}")),
         }
     }
 }
 
+/// Daemon events that can run a command, see `[hooks]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    PreChange,
+    PostChange,
+    Pause,
+    Resume,
+    Reload,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PreChange => "pre_change",
+            Self::PostChange => "post_change",
+            Self::Pause => "on_pause",
+            Self::Resume => "on_resume",
+            Self::Reload => "on_reload",
+        })
+    }
+}
+
+/// How the queue is ordered.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Order {
+    Shuffle,
+    Sorted,
+    // Each wallpaper's colours are close to the previous one's.
+    Colour,
+}
+
+impl Display for Order {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Shuffle => "shuffle",
+            Self::Sorted => "sorted",
+            Self::Colour => "colour",
+        })
+    }
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "shuffle" => Ok(Self::Shuffle),
+            "sorted" => Ok(Self::Sorted),
+            "colour" | "color" => Ok(Self::Colour),
+            _ => Err(format!("Invalid order: {s}")),
+        }
+    }
+}
+
+/// How long a wallpaper stays, picked at random between `min` and `max` for every wallpaper.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(try_from = "RawInterval", into = "RawInterval")]
+pub struct Interval {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Interval {
+    pub fn fixed(duration: Duration) -> Self {
+        Self {
+            min: duration,
+            max: duration,
+        }
+    }
+}
+
+// Intervals in the config are seconds, durations like "1h30m", or a range of either.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawInterval {
+    Fixed(RawDuration),
+    Range { min: RawDuration, max: RawDuration },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Secs(u64),
+    Text(String),
+}
+
+impl TryFrom<RawDuration> for Duration {
+    type Error = String;
+
+    fn try_from(raw: RawDuration) -> Result<Self, Self::Error> {
+        match raw {
+            RawDuration::Secs(secs) => Ok(Duration::from_secs(secs)),
+            RawDuration::Text(text) => utils::parse_duration(&text),
+        }
+    }
+}
+
+impl TryFrom<RawInterval> for Interval {
+    type Error = String;
+
+    fn try_from(raw: RawInterval) -> Result<Self, Self::Error> {
+        match raw {
+            RawInterval::Fixed(duration) => Ok(Self::fixed(duration.try_into()?)),
+            RawInterval::Range { min, max } => {
+                let (min, max): (Duration, Duration) = (min.try_into()?, max.try_into()?);
+                if min > max {
+                    return Err(format!(
+                        "Invalid interval: min ({}s) is longer than max ({}s)",
+                        min.as_secs(),
+                        max.as_secs()
+                    ));
+                }
+                Ok(Self { min, max })
+            }
+        }
+    }
+}
+
+impl From<Interval> for RawInterval {
+    fn from(interval: Interval) -> Self {
+        let secs = |duration: Duration| RawDuration::Secs(duration.as_secs());
+        if interval.min == interval.max {
+            Self::Fixed(secs(interval.min))
+        } else {
+            Self::Range {
+                min: secs(interval.min),
+                max: secs(interval.max),
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FilterMethod {
     Nearest,
@@ -11,39 +11,83 @@ use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
+use std::sync::OnceLock;
+use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
 
 use nix::fcntl::Flock;
 use nix::fcntl::FlockArg;
 
 use crate::commands::Commands;
+use crate::systemd;
 use crate::utils;
 use crate::utils::Dirs;
 
+// How long the server waits for the daemon to answer a command that expects a reply.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+// How long to wait for a running instance to shut down when replacing it.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(30);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Set with `set_socket_path` to run or talk to an instance other than the default one.
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// A command received over IPC, with where to send the reply if it expects one.
+#[derive(Debug)]
+pub struct Request {
+    pub command: Commands,
+    pub reply: Option<Sender<String>>,
+}
+
+impl Request {
+    /// Answers the request. The client may have given up waiting already, in which case the reply
+    /// is dropped.
+    pub fn reply(&self, reply: String) {
+        if let Some(tx) = &self.reply {
+            let _ = tx.send(reply);
+        }
+    }
+}
+
+impl From<Commands> for Request {
+    fn from(command: Commands) -> Self {
+        Self {
+            command,
+            reply: None,
+        }
+    }
+}
+
+/// Held by the running instance, so there is only ever one.
+pub struct InstanceLock {
+    _flock: Flock<File>,
+}
+
 pub struct IpcServer {
     socket_path: PathBuf,
     // Guard ensures we always cleanup the socket file: $XDG_RUNTIME_DIR/walrus.
     _guard: IpcGuard,
     // To hold the lock for the entire lifetime of the struct.
-    _lock: Option<Flock<File>>,
+    _lock: InstanceLock,
 }
 
 impl IpcServer {
-    fn new(socket_path: PathBuf, lock_path: PathBuf) -> Self {
-        let flock = acquire_lock(&lock_path);
+    fn new(socket_path: PathBuf, lock: InstanceLock) -> Self {
         let mut guard = IpcGuard::new();
         guard.add_path(&socket_path);
 
         Self {
             socket_path,
             _guard: guard,
-            _lock: Some(flock),
+            _lock: lock,
         }
     }
 
-    fn start(&self, tx: Sender<Commands>) -> JoinHandle<()> {
+    fn start(&self, tx: Sender<Request>) -> JoinHandle<()> {
         if self.socket_path.exists() {
             tracing::debug!("Socket file already exists (cleanup may have failed)");
 
@@ -54,15 +98,18 @@ impl IpcServer {
         }
 
         let listener = UnixListener::bind(&self.socket_path).expect("Failed to bind socket");
+        Self::serve(listener, tx)
+    }
 
+    fn serve(listener: UnixListener, tx: Sender<Request>) -> JoinHandle<()> {
         thread::spawn(move || {
             for stream in listener.incoming() {
-                let Ok(mut stream) = stream else {
+                let Ok(stream) = stream else {
                     tracing::error!("Error accepting connection: {}", stream.unwrap_err());
                     continue;
                 };
 
-                if parse_stream(&mut stream, &tx).is_break() {
+                if parse_stream(stream, &tx).is_break() {
                     break;
                 }
             }
@@ -82,6 +129,15 @@ impl IpcClient {
     }
 
     fn send(&self, command: Commands) -> io::Result<()> {
+        self.connect_and_send(&command).map(drop)
+    }
+
+    fn request(&self, command: Commands) -> io::Result<String> {
+        let mut stream = self.connect_and_send(&command)?;
+        read_reply(&mut stream)
+    }
+
+    fn connect_and_send(&self, command: &Commands) -> io::Result<UnixStream> {
         let mut stream = UnixStream::connect(&self.socket_path)?;
         let cmd = command
             .to_bytes()
@@ -92,7 +148,7 @@ impl IpcClient {
         let len = (cmd.len() as u16).to_le_bytes();
         stream.write_all(&len)?;
         stream.write_all(&cmd)?;
-        Ok(())
+        Ok(stream)
     }
 }
 
@@ -123,31 +179,43 @@ impl Drop for IpcGuard {
     }
 }
 
-fn acquire_lock(lock_path: &Path) -> Flock<File> {
-    let lock_file = OpenOptions::new()
-        .mode(0o640)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(lock_path)
-        .expect("Error creating lock file");
-    match Flock::lock(lock_file, FlockArg::LockExclusiveNonblock) {
-        Ok(flock) => {
+fn acquire_lock(lock_path: &Path) -> InstanceLock {
+    match try_lock(lock_path) {
+        Ok(Some(flock)) => {
             tracing::debug!("Successfully acquired lock file");
-            flock
+            InstanceLock { _flock: flock }
         }
-        Err((_, nix::Error::EWOULDBLOCK)) => {
+        Ok(None) => {
             tracing::error!("An instance is already running (lock file is locked)");
+            tracing::error!("Use --replace to take its place");
             process::exit(1)
         }
-        Err((_, e)) => {
+        Err(e) => {
             tracing::error!("Error locking lock file: {e}");
             process::exit(1)
         }
     }
 }
 
-fn parse_stream<R: Read>(stream: &mut R, tx: &Sender<Commands>) -> ControlFlow<()> {
+// Returns `None` if another instance holds the lock.
+fn try_lock(lock_path: &Path) -> io::Result<Option<Flock<File>>> {
+    let lock_file = OpenOptions::new()
+        .mode(0o640)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(lock_path)?;
+    match Flock::lock(lock_file, FlockArg::LockExclusiveNonblock) {
+        Ok(flock) => Ok(Some(flock)),
+        Err((_, nix::Error::EWOULDBLOCK)) => Ok(None),
+        Err((_, e)) => Err(e.into()),
+    }
+}
+
+fn parse_stream<S: Read + Write + Send + 'static>(
+    mut stream: S,
+    tx: &Sender<Request>,
+) -> ControlFlow<()> {
     let mut len_buffer = [0u8; 2];
     if let Err(e) = stream.read_exact(&mut len_buffer) {
         tracing::error!("Error reading length prefix: {e}");
@@ -163,18 +231,70 @@ fn parse_stream<R: Read>(stream: &mut R, tx: &Sender<Commands>) -> ControlFlow<(
 
     if let Some(command) = Commands::from_bytes(&cmd_buffer) {
         tracing::debug!("IPC received {:?} command", command);
-        let _ = tx.send(command.clone());
 
-        return match command {
-            Commands::Shutdown => ControlFlow::Break(()),
-            _ => ControlFlow::Continue(()),
+        let shutdown = matches!(command, Commands::Shutdown);
+        if command.expects_reply() {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            let dedupe = matches!(command, Commands::Dedupe);
+            let _ = tx.send(Request {
+                command,
+                reply: Some(reply_tx),
+            });
+
+            // Waits on its own thread, so other clients aren't held up meanwhile.
+            thread::spawn(move || {
+                let reply = reply_rx.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|e| {
+                    tracing::error!("No reply from daemon: {e}");
+                    // Finding duplicates hashes every wallpaper the first time, which can take a
+                    // while. The daemon keeps at it and caches the hashes.
+                    if dedupe {
+                        String::from("Still looking for duplicates, try again in a bit")
+                    } else {
+                        String::from("No reply from walrus-daemon")
+                    }
+                });
+                if let Err(e) = write_reply(&mut stream, &reply) {
+                    tracing::error!("Error writing reply: {e}");
+                }
+            });
+        } else {
+            let _ = tx.send(command.into());
+        }
+
+        return if shutdown {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
         };
     }
 
     ControlFlow::Continue(())
 }
 
+// Replies are plain text and can get long (e.g. listings), so they use a u32 length prefix.
+fn write_reply<W: Write>(stream: &mut W, reply: &str) -> io::Result<()> {
+    let len = (reply.len() as u32).to_le_bytes();
+    stream.write_all(&len)?;
+    stream.write_all(reply.as_bytes())
+}
+
+fn read_reply<R: Read>(stream: &mut R) -> io::Result<String> {
+    let mut len_buffer = [0u8; 4];
+    stream.read_exact(&mut len_buffer)?;
+    let len = u32::from_le_bytes(len_buffer);
+
+    let mut reply_buffer = vec![0u8; len as usize];
+    stream.read_exact(&mut reply_buffer)?;
+    String::from_utf8(reply_buffer).map_err(io::Error::other)
+}
+
 fn get_paths() -> (PathBuf, PathBuf) {
+    if let Some(socket_path) = SOCKET_PATH.get() {
+        let mut lock_path = socket_path.clone().into_os_string();
+        lock_path.push(".lock");
+        return (socket_path.clone(), lock_path.into());
+    }
+
     let runtime_dir = utils::get_dir(Dirs::Runtime).unwrap_or_else(|e| {
         tracing::error!("Error getting runtime directory: {}", e);
         process::exit(1)
@@ -184,10 +304,61 @@ fn get_paths() -> (PathBuf, PathBuf) {
     (socket_path, lock_path)
 }
 
-pub fn start_server(tx: Sender<Commands>) -> IpcServer {
-    tracing::debug!("Starting IPC server");
+/// Uses `path` as the IPC socket instead of `$XDG_RUNTIME_DIR/walrus`, with the lock file next to
+/// it. Has to be called before anything else in this module.
+pub fn set_socket_path(path: PathBuf) {
+    if SOCKET_PATH.set(path).is_err() {
+        tracing::warn!("IPC socket path was already set");
+    }
+}
+
+/// Takes the instance lock, exiting if another instance holds it. Has to be held before loading
+/// anything the running instance might still write, like the cache.
+pub fn lock_instance() -> InstanceLock {
+    let (_, lock_path) = get_paths();
+    acquire_lock(&lock_path)
+}
+
+/// Asks the running instance to shut down and takes its lock once it has released it, so a new one
+/// can take its place.
+pub fn replace() -> io::Result<InstanceLock> {
     let (socket_path, lock_path) = get_paths();
-    let server = IpcServer::new(socket_path, lock_path);
+    match IpcClient::new(socket_path).send(Commands::Shutdown) {
+        Ok(()) => tracing::info!("Asked the running instance to shut down"),
+        Err(e) => tracing::debug!("No instance to replace: {e}"),
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < REPLACE_TIMEOUT {
+        if let Some(flock) = try_lock(&lock_path)? {
+            return Ok(InstanceLock { _flock: flock });
+        }
+        thread::sleep(LOCK_POLL_INTERVAL);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "the running instance didn't shut down",
+    ))
+}
+
+/// Starts serving commands, holding on to `lock` until the returned server is dropped.
+pub fn start_server(tx: Sender<Request>, lock: InstanceLock) -> IpcServer {
+    tracing::debug!("Starting IPC server");
+    let (socket_path, _) = get_paths();
+
+    // With socket activation the socket belongs to systemd, so it's left in place on exit.
+    if let Some(listener) = systemd::listener() {
+        tracing::debug!("Using socket passed in by systemd");
+        let server = IpcServer {
+            socket_path,
+            _guard: IpcGuard::new(),
+            _lock: lock,
+        };
+        IpcServer::serve(listener, tx);
+        return server;
+    }
+
+    let server = IpcServer::new(socket_path, lock);
     server.start(tx);
 
     server
@@ -202,12 +373,23 @@ pub fn send_command(command: Commands) -> io::Result<()> {
     client.send(command)
 }
 
+/// Sends a command and waits for the daemon's reply.
+pub fn send_request(command: Commands) -> io::Result<String> {
+    tracing::debug!("IPC requesting {:?} command", command);
+
+    let (socket_path, _) = get_paths();
+
+    let client = IpcClient::new(socket_path);
+    client.request(command)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Mutex;
     use std::sync::mpsc;
 
     use super::*;
+    use crate::commands::CategoryAction;
 
     // These tests will panic if run in parallel, so I'm using a Mutex to ensure they don't.
     // The reason this works is because the call to .lock() is blocking.
@@ -227,14 +409,14 @@ mod tests {
         let (tx, rx) = mpsc::channel();
 
         let (socket_path, lock_path) = get_paths();
-        let server = IpcServer::new(socket_path.clone(), lock_path);
+        let server = IpcServer::new(socket_path.clone(), acquire_lock(&lock_path));
         let handle = server.start(tx.clone());
 
         let cmd = Commands::Next;
         let client = IpcClient::new(socket_path);
         client.send(cmd.clone()).unwrap();
 
-        let rx_cmd = rx.recv().unwrap();
+        let rx_cmd = rx.recv().unwrap().command;
 
         assert_eq!(cmd.to_bytes(), rx_cmd.to_bytes());
 
@@ -249,7 +431,7 @@ mod tests {
         let (tx, rx) = mpsc::channel();
 
         let (socket_path, lock_path) = get_paths();
-        let server = IpcServer::new(socket_path.clone(), lock_path);
+        let server = IpcServer::new(socket_path.clone(), acquire_lock(&lock_path));
         let handle = server.start(tx.clone());
 
         let cmd = Commands::Categorise {
@@ -258,7 +440,7 @@ mod tests {
         let client = IpcClient::new(socket_path);
         client.send(cmd.clone()).unwrap();
 
-        let rx_cmd = rx.recv().unwrap();
+        let rx_cmd = rx.recv().unwrap().command;
 
         assert_eq!(cmd.to_bytes(), rx_cmd.to_bytes());
 
@@ -268,13 +450,16 @@ mod tests {
 
     #[test]
     fn test_stream_parsing() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (mut client, server) = UnixStream::pair().unwrap();
         let (tx, rx) = mpsc::channel();
 
         let cmds = [
             Commands::Next,
             Commands::Previous,
-            Commands::Pause,
+            Commands::Pause {
+                duration: None,
+                until: None,
+            },
             Commands::Resume,
             Commands::Reload,
         ];
@@ -285,11 +470,87 @@ mod tests {
             client.write_all(&len).unwrap();
             client.write_all(&bytes).unwrap();
 
-            let control_flow = parse_stream(&mut server, &tx);
+            let control_flow = parse_stream(server.try_clone().unwrap(), &tx);
             assert!(!control_flow.is_break());
 
             let received = rx.recv().unwrap();
-            assert_eq!(cmd.to_bytes(), received.to_bytes());
+            assert_eq!(cmd.to_bytes(), received.command.to_bytes());
+            assert!(received.reply.is_none());
         }
     }
+
+    #[test]
+    fn test_reply_pairing() {
+        let (tx, rx) = mpsc::channel();
+        let request = |category: &str| {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            let bytes = Commands::Category {
+                action: CategoryAction::Remove {
+                    category: category.into(),
+                },
+            }
+            .to_bytes()
+            .unwrap();
+            client
+                .write_all(&(bytes.len() as u16).to_le_bytes())
+                .unwrap();
+            client.write_all(&bytes).unwrap();
+            assert!(!parse_stream(server, &tx).is_break());
+            client
+        };
+        let mut first = request("first");
+        let mut second = request("second");
+
+        // Answered out of order, each reply still goes to the client that asked.
+        let first_request = rx.recv().unwrap();
+        rx.recv().unwrap().reply(String::from("second"));
+        first_request.reply(String::from("first"));
+
+        assert_eq!(read_reply(&mut second).unwrap(), "second");
+        assert_eq!(read_reply(&mut first).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let lock_path = std::env::temp_dir().join(format!("walrus-lock-{}", process::id()));
+
+        let lock = try_lock(&lock_path).unwrap();
+        assert!(lock.is_some());
+        assert!(try_lock(&lock_path).unwrap().is_none());
+
+        // Free again once the instance holding it is gone.
+        drop(lock);
+        assert!(try_lock(&lock_path).unwrap().is_some());
+        fs::remove_file(&lock_path).unwrap();
+    }
+
+    #[test]
+    fn test_ipc_reply() {
+        let _lock = LOCK.lock().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+
+        let (socket_path, lock_path) = get_paths();
+        let server = IpcServer::new(socket_path.clone(), acquire_lock(&lock_path));
+        let handle = server.start(tx.clone());
+
+        // Stand-in for the daemon answering the request.
+        let daemon = thread::spawn(move || {
+            let request = rx.recv().unwrap();
+            assert!(request.command.expects_reply());
+            request.reply(String::from("Favourites"));
+        });
+
+        let client = IpcClient::new(socket_path);
+        let reply = client
+            .request(Commands::Category {
+                action: CategoryAction::List,
+            })
+            .unwrap();
+        assert_eq!(reply, "Favourites");
+        daemon.join().unwrap();
+
+        client.send(Commands::Shutdown).unwrap();
+        handle.join().unwrap();
+    }
 }
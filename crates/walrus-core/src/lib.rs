@@ -1,5 +1,6 @@
 pub mod commands;
 pub mod config;
 pub mod ipc;
+pub mod systemd;
 pub mod utils;
 pub mod wayland;
@@ -0,0 +1,80 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::net::UnixListener;
+use std::process;
+
+use nix::fcntl::FcntlArg;
+use nix::fcntl::FdFlag;
+use nix::fcntl::fcntl;
+
+// The first file descriptor passed in by socket activation, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Tells systemd about the daemon's state, e.g. `READY=1` or `STATUS=...`, see sd_notify(3). Does
+/// nothing when not running as a `Type=notify` service.
+pub fn notify(state: &str) {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = notify_to(&socket, state) {
+        tracing::warn!("Error notifying systemd: {e}");
+    }
+}
+
+fn notify_to(socket: &OsStr, state: &str) -> io::Result<()> {
+    // Names starting with @ are abstract sockets.
+    let addr = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// The listening socket passed in by systemd socket activation, if any, see sd_listen_fds(3).
+pub fn listener() -> Option<UnixListener> {
+    let pid = env::var("LISTEN_PID").ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?;
+    // The variables are inherited by child processes, which must not take the socket.
+    if pid.parse() != Ok(process::id()) || fds.parse::<u32>().ok()? < 1 {
+        return None;
+    }
+
+    // SAFETY: systemd passes the socket as the first descriptor after stdin, stdout and stderr,
+    // and LISTEN_PID makes sure it was meant for this process. Nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    // Hooks and swww shouldn't inherit it.
+    if let Err(e) = fcntl(LISTEN_FDS_START, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+        tracing::warn!("Error setting close-on-exec on the activated socket: {e}");
+    }
+    Some(UnixListener::from(fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let path = env::temp_dir().join(format!("walrus-notify-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+        let mut buffer = [0u8; 16];
+        let len = systemd.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -5,14 +5,22 @@ use std::fmt::Display;
 use std::fs;
 use std::fs::File;
 use std::io;
+use std::mem::MaybeUninit;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use nix::libc;
 
 pub const APPNAME: &str = "walrus";
+// Under the state directory, daily log files are named after the app with the date appended.
+pub const LOG_DIR: &str = "logs";
 
 pub enum Dirs {
     Bin,     // Executable dir
-    Cache,   // Might need in the future
+    Cache,   // Image metadata index
     Config,  //
     Data,    //
     Home,    // Just $HOME
@@ -121,3 +129,147 @@ pub fn get_config_file<P: AsRef<Path>>(filename: P) -> Result<PathBuf, DirError>
     }
     Ok(config_file)
 }
+
+/// Parses a duration such as `90`, `30s`, `5m`, `1h30m` or `1d`. A number without a unit is
+/// seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {duration:?}, expected e.g. 30s, 5m or 1h30m");
+    let duration = duration.trim();
+    if let Ok(secs) = duration.parse() {
+        return Ok(Duration::from_secs(secs));
+    }
+    if duration.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut secs = 0u64;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let letters = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match rest[..letters].trim() {
+            "s" | "sec" | "secs" => 1,
+            "m" | "min" | "mins" => 60,
+            "h" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[letters..];
+
+        secs = value
+            .checked_mul(unit)
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(invalid)?;
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parses a time of day such as `18:00` and returns how long it is until the clock next shows it.
+pub fn parse_time_until(time: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid time {time:?}, expected e.g. 18:00");
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: i64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs(secs_until(
+        unix_time(),
+        hours * 60 * 60 + minutes * 60,
+        utc_offset,
+    )))
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs().try_into().unwrap_or(i64::MAX))
+}
+
+/// How far local time is ahead of UTC at `time` (in seconds since the Unix epoch), in seconds.
+pub fn utc_offset(time: i64) -> i64 {
+    let time: libc::time_t = time;
+    let mut tm = MaybeUninit::<libc::tm>::zeroed();
+    // SAFETY: localtime_r only writes to `tm`, which is initialised when it doesn't return null.
+    // Nothing in walrus changes the time zone while it runs.
+    unsafe {
+        if libc::localtime_r(&raw const time, tm.as_mut_ptr()).is_null() {
+            return 0;
+        }
+        tm.assume_init().tm_gmtoff
+    }
+}
+
+// Seconds from `now` (in seconds since the Unix epoch) until the next time the clock shows
+// `second_of_day`, a full day if it already does. `utc_offset` gives the offset at a point in time,
+// which changes when daylight saving time starts or ends in between.
+fn secs_until(now: i64, second_of_day: i64, utc_offset: impl Fn(i64) -> i64) -> u64 {
+    const DAY: i64 = 24 * 60 * 60;
+    let local = now + utc_offset(now);
+    let mut secs = (second_of_day - local.rem_euclid(DAY)).rem_euclid(DAY);
+    if secs == 0 {
+        secs = DAY;
+    }
+
+    // Twice, in case the first correction moves the target across the change.
+    let naive = secs;
+    for _ in 0..2 {
+        secs = naive - (utc_offset(now + secs) - utc_offset(now));
+    }
+    // The clock jumped past the time already.
+    if secs <= 0 {
+        secs += DAY;
+    }
+    secs.unsigned_abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("300"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1d 2h"), Ok(Duration::from_secs(93_600)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("5 parsecs").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_time_until() {
+        // 17:30 on some day, in UTC.
+        let now = 20_000 * 24 * 60 * 60 + 17 * 60 * 60 + 30 * 60;
+        let utc = |_| 0;
+        assert_eq!(secs_until(now, 18 * 60 * 60, utc), 30 * 60);
+        // Times that already passed are tomorrow.
+        assert_eq!(secs_until(now, 9 * 60 * 60, utc), 15 * 60 * 60 + 30 * 60);
+        assert_eq!(secs_until(now, 17 * 60 * 60 + 30 * 60, utc), 24 * 60 * 60);
+
+        // Clocks go forward an hour at 01:00 UTC tomorrow, 02:00 local time.
+        let change = now + 7 * 60 * 60 + 30 * 60;
+        let summer = |time| if time < change { 60 * 60 } else { 2 * 60 * 60 };
+        // 18:30 now, so 09:00 is an hour closer.
+        assert_eq!(secs_until(now, 9 * 60 * 60, summer), 13 * 60 * 60 + 30 * 60);
+        // Before the change it's still the same.
+        assert_eq!(secs_until(now, 19 * 60 * 60, summer), 30 * 60);
+        // And back again at 03:00, 19:30 now, so 09:00 is an hour further away.
+        let winter = |time| if time < change { 2 * 60 * 60 } else { 60 * 60 };
+        assert_eq!(secs_until(now, 9 * 60 * 60, winter), 14 * 60 * 60 + 30 * 60);
+        assert!(parse_time_until("25:00").is_err());
+        assert!(parse_time_until("18").is_err());
+    }
+}
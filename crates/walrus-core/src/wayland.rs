@@ -3,6 +3,7 @@ use std::error::Error;
 use smithay_client_toolkit::delegate_output;
 use smithay_client_toolkit::delegate_registry;
 use smithay_client_toolkit::output::OutputHandler;
+use smithay_client_toolkit::output::OutputInfo;
 use smithay_client_toolkit::output::OutputState;
 use smithay_client_toolkit::registry::ProvidesRegistryState;
 use smithay_client_toolkit::registry::RegistryState;
@@ -12,6 +13,7 @@ use wayland_client::EventQueue;
 use wayland_client::QueueHandle;
 use wayland_client::globals::registry_queue_init;
 use wayland_client::protocol::wl_output;
+use wayland_client::protocol::wl_output::Transform;
 
 use crate::config;
 
@@ -66,53 +68,16 @@ impl OutputHandler for WaylandState {
     }
 
     fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, o: wl_output::WlOutput) {
-        if let Some(info) = self.output_state.info(&o)
-            && let Some(mode) = info
-                .modes
-                .iter()
-                .find(|m| m.current || m.preferred)
-                .or_else(|| info.modes.first())
-        {
-            let monitor = config::MonitorInfo {
-                refresh_rate: mode.refresh_rate as f32 / 1000.0,
-                resolution: config::Resolution {
-                    width: mode.dimensions.0,
-                    height: mode.dimensions.1,
-                },
-                id: info.id,
-            };
+        if let Some(monitor) = self.output_state.info(&o).as_ref().and_then(monitor_info) {
             self.outputs.push(monitor);
         }
     }
 
     fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, o: wl_output::WlOutput) {
-        if let Some(info) = self.output_state.info(&o)
-            && let Some(mode) = info
-                .modes
-                .iter()
-                .find(|m| m.current || m.preferred)
-                .or_else(|| info.modes.first())
-        {
-            let monitor = config::MonitorInfo {
-                refresh_rate: mode.refresh_rate as f32 / 1000.0,
-                resolution: config::Resolution {
-                    width: mode.dimensions.0,
-                    height: mode.dimensions.1,
-                },
-                id: info.id,
-            };
-
-            let exists = self.outputs.iter_mut().any(|existing| {
-                if existing.id == info.id {
-                    existing.refresh_rate = monitor.refresh_rate;
-                    existing.resolution = monitor.resolution;
-                    true
-                } else {
-                    false
-                }
-            });
-
-            if !exists {
+        if let Some(monitor) = self.output_state.info(&o).as_ref().and_then(monitor_info) {
+            if let Some(existing) = self.outputs.iter_mut().find(|m| m.id == monitor.id) {
+                *existing = monitor;
+            } else {
                 self.outputs.push(monitor);
             }
         }
@@ -125,6 +90,42 @@ impl OutputHandler for WaylandState {
     }
 }
 
+fn monitor_info(info: &OutputInfo) -> Option<config::MonitorInfo> {
+    let mode = info
+        .modes
+        .iter()
+        .find(|m| m.current || m.preferred)
+        .or_else(|| info.modes.first())?;
+
+    let rotated = matches!(
+        info.transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    );
+    // Without xdg-output the logical size has to be worked out from the mode.
+    let logical_size = info.logical_size.unwrap_or_else(|| {
+        let (width, height) = mode.dimensions;
+        let scale = info.scale_factor.max(1);
+        if rotated {
+            (height / scale, width / scale)
+        } else {
+            (width / scale, height / scale)
+        }
+    });
+
+    Some(config::MonitorInfo {
+        refresh_rate: mode.refresh_rate as f32 / 1000.0,
+        resolution: config::Resolution {
+            width: mode.dimensions.0,
+            height: mode.dimensions.1,
+        },
+        id: info.id,
+        name: info.name.clone(),
+        rotated,
+        position: info.logical_position.unwrap_or(info.location),
+        logical_size,
+    })
+}
+
 impl ProvidesRegistryState for WaylandState {
     registry_handlers!(OutputState);
 
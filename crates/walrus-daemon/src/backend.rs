@@ -0,0 +1,192 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::Read;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+// How often a running command is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// Delay before the first retry, doubled for every retry after it.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub enum BackendError {
+    Spawn(io::Error),
+    Wait(io::Error),
+    Timeout(Duration),
+    Failed { status: ExitStatus, stderr: String },
+}
+
+impl BackendError {
+    // Trying again doesn't help when the program can't be started at all, e.g. a wrong swww_path.
+    fn is_permanent(&self) -> bool {
+        matches!(self, Self::Spawn(_))
+    }
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(e) => write!(f, "couldn't start swww: {e}"),
+            Self::Wait(e) => write!(f, "error waiting for swww: {e}"),
+            Self::Timeout(timeout) => write!(f, "swww timed out after {timeout:?}"),
+            Self::Failed { status, stderr } if stderr.is_empty() => {
+                write!(f, "swww failed ({status})")
+            }
+            Self::Failed { status, stderr } => write!(f, "swww failed ({status}): {stderr}"),
+        }
+    }
+}
+
+impl Error for BackendError {}
+
+/// A running backend command, with its output collected in the background.
+pub struct Job {
+    child: Child,
+    stdout: Option<JoinHandle<String>>,
+    stderr: Option<JoinHandle<String>>,
+    started: Instant,
+}
+
+impl Job {
+    pub fn spawn(command: &mut Command) -> Result<Self, BackendError> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(BackendError::Spawn)?;
+        let stdout = child.stdout.take().map(read);
+        let stderr = child.stderr.take().map(read);
+
+        Ok(Self {
+            child,
+            stdout,
+            stderr,
+            started: Instant::now(),
+        })
+    }
+
+    /// Waits for the command to finish and returns what it printed. It is killed once it has been
+    /// running for `timeout`, so a hung swww can't hold up the daemon.
+    pub fn wait(mut self, timeout: Duration) -> Result<String, BackendError> {
+        let status = loop {
+            match self.child.try_wait().map_err(BackendError::Wait)? {
+                Some(status) => break status,
+                None if self.started.elapsed() >= timeout => {
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    return Err(BackendError::Timeout(timeout));
+                }
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        };
+
+        let output = |pipe: Option<JoinHandle<String>>| {
+            pipe.and_then(|pipe| pipe.join().ok()).unwrap_or_default()
+        };
+        let stdout = output(self.stdout.take());
+        let stderr = output(self.stderr.take()).trim().to_owned();
+        if status.success() {
+            if !stderr.is_empty() {
+                tracing::debug!("swww: {stderr}");
+            }
+            Ok(stdout)
+        } else {
+            Err(BackendError::Failed { status, stderr })
+        }
+    }
+}
+
+// Read on another thread, a command writing a lot would block on a full pipe otherwise.
+fn read<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = pipe.read_to_string(&mut output);
+        output
+    })
+}
+
+/// Calls `attempt` until it succeeds, at most `retries` more times after the first failure, and
+/// waits longer before every retry.
+pub fn retry<F>(retries: u32, mut attempt: F) -> Result<(), BackendError>
+where
+    F: FnMut() -> Result<(), BackendError>,
+{
+    let mut delay = RETRY_DELAY;
+    for _ in 0..retries {
+        match attempt() {
+            Err(e) if !e.is_permanent() => {
+                tracing::warn!("{e}, retrying in {delay:?}");
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    attempt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn test_job() {
+        let timeout = Duration::from_secs(5);
+        let output = Job::spawn(&mut sh("echo DP-1")).unwrap().wait(timeout);
+        assert_eq!(output.unwrap(), "DP-1\n");
+
+        let failed = Job::spawn(&mut sh("echo 'no such output' >&2; exit 1"))
+            .unwrap()
+            .wait(timeout);
+        assert!(
+            matches!(failed, Err(BackendError::Failed { ref stderr, .. }) if stderr == "no such output")
+        );
+
+        let start = Instant::now();
+        let hung = Job::spawn(&mut sh("sleep 10"))
+            .unwrap()
+            .wait(Duration::from_millis(100));
+        assert!(matches!(hung, Err(BackendError::Timeout(_))));
+        assert!(start.elapsed() < timeout);
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut attempts = 0;
+        let result = retry(2, || {
+            attempts += 1;
+            Job::spawn(&mut sh(&format!("test {attempts} = 2")))?
+                .wait(Duration::from_secs(5))
+                .map(drop)
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+
+        // A missing program isn't retried.
+        let mut attempts = 0;
+        let result = retry(2, || {
+            attempts += 1;
+            Job::spawn(&mut Command::new("/nonexistent/swww"))?
+                .wait(Duration::from_secs(5))
+                .map(drop)
+        });
+        assert!(matches!(result, Err(BackendError::Spawn(_))));
+        assert_eq!(attempts, 1);
+    }
+}
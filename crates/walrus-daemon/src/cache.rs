@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::hash_map;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use bincode::Decode;
+use bincode::Encode;
+use bincode::config;
+use walrus_core::utils;
+use walrus_core::utils::Dirs;
+
+use crate::hash;
+use crate::magic;
+use crate::magic::ImageFormat;
+use crate::palette;
+use crate::palette::Color;
+
+// Bump this whenever Entry changes so old caches get discarded instead of misread.
+const CACHE_VERSION: u32 = 3;
+const CACHE_FILE: &str = "index";
+
+/// Per-file data that is expensive to compute, stored in `$XDG_CACHE_HOME/walrus`. Entries are
+/// keyed by path and thrown away as soon as the file's modification time or size changes, so a
+/// rescan only has to read files that are new or changed.
+///
+/// The result of the last scan is kept too, which lets the daemon start without walking the
+/// wallpaper directory first.
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: Option<PathBuf>,
+    entries: HashMap<PathBuf, Entry>,
+    last_scan: Option<Scan>,
+    dirty: bool,
+}
+
+#[derive(Clone, Debug, Decode, Default, Encode)]
+pub struct Entry {
+    modified: u64,
+    size: u64,
+    // Whether the file has been looked at yet, `format` is `None` for files that aren't images.
+    detected: bool,
+    format: Option<ImageFormat>,
+    dimensions: Option<(u32, u32)>,
+    pub content_hash: Option<String>,
+    pub perceptual_hash: Option<u64>,
+    pub palette: Option<Vec<Color>>,
+}
+
+#[derive(Clone, Debug, Decode, Encode)]
+struct Scan {
+    dir: PathBuf,
+    wallpapers: Vec<PathBuf>,
+}
+
+#[derive(Decode, Encode)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<PathBuf, Entry>,
+    last_scan: Option<Scan>,
+}
+
+impl Cache {
+    /// Loads the cache, starting out empty if it doesn't exist or can't be read.
+    pub fn load() -> Self {
+        let path = match utils::get_dir(Dirs::Cache) {
+            Ok(dir) => dir.join(utils::APPNAME).join(CACHE_FILE),
+            Err(e) => {
+                tracing::warn!("Not caching image metadata: {e}");
+                return Self::default();
+            }
+        };
+
+        let file = match fs::read(&path) {
+            Ok(bytes) => decode(&bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!("Error reading cache, starting over: {e}");
+                None
+            }
+        };
+
+        let (entries, last_scan) = file.map_or_else(Default::default, |f| (f.entries, f.last_scan));
+        tracing::debug!("Loaded {} cache entries", entries.len());
+        Self {
+            path: Some(path),
+            entries,
+            last_scan,
+            dirty: false,
+        }
+    }
+
+    /// Writes the cache to disk if anything changed since it was loaded.
+    pub fn save(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty {
+            return;
+        }
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self.entries.clone(),
+            last_scan: self.last_scan.clone(),
+        };
+        let result = bincode::encode_to_vec(file, config::standard())
+            .map_err(io::Error::other)
+            .and_then(|bytes| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // Write to a temporary file first so a crash can't leave a truncated cache behind.
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, bytes)?;
+                fs::rename(&tmp, path)
+            });
+
+        match result {
+            Ok(()) => self.dirty = false,
+            Err(e) => tracing::error!("Error writing cache: {e}"),
+        }
+    }
+
+    /// Copy to scan into without holding on to the shared cache. It is never written to disk
+    /// itself, `merge` it back instead.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            path: None,
+            entries: self.entries.clone(),
+            last_scan: self.last_scan.clone(),
+            dirty: false,
+        }
+    }
+
+    /// Takes over what was computed in a snapshot. Entries computed here in the meantime are
+    /// kept, except for files under the snapshot's scanned directory that it no longer found.
+    pub fn merge(&mut self, other: Self) {
+        if !other.dirty {
+            return;
+        }
+
+        if let Some(scan) = &other.last_scan {
+            self.entries
+                .retain(|path, _| !path.starts_with(&scan.dir) || other.entries.contains_key(path));
+        }
+        for (path, theirs) in other.entries {
+            match self.entries.entry(path) {
+                hash_map::Entry::Occupied(mut ours)
+                    if ours.get().modified == theirs.modified && ours.get().size == theirs.size =>
+                {
+                    ours.get_mut().fill(theirs);
+                }
+                hash_map::Entry::Occupied(mut ours) => *ours.get_mut() = theirs,
+                hash_map::Entry::Vacant(ours) => {
+                    ours.insert(theirs);
+                }
+            }
+        }
+        if other.last_scan.is_some() {
+            self.last_scan = other.last_scan;
+        }
+        self.dirty = true;
+    }
+
+    /// Returns the up to date entry for the file, resetting it if the file changed.
+    pub fn entry(&mut self, path: &Path) -> io::Result<&mut Entry> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let size = metadata.len();
+
+        let entry = self.entries.entry(path.to_owned()).or_default();
+        if entry.modified != modified || entry.size != size {
+            *entry = Entry {
+                modified,
+                size,
+                ..Entry::default()
+            };
+            self.dirty = true;
+        }
+        Ok(entry)
+    }
+
+    /// Wallpapers found the last time `dir` was scanned.
+    pub fn wallpapers(&self, dir: &Path) -> Option<&[PathBuf]> {
+        self.last_scan
+            .as_ref()
+            .filter(|scan| scan.dir == dir)
+            .map(|scan| scan.wallpapers.as_slice())
+    }
+
+    pub fn set_wallpapers(&mut self, dir: &Path, wallpapers: Vec<PathBuf>) {
+        self.last_scan = Some(Scan {
+            dir: dir.to_owned(),
+            wallpapers,
+        });
+        self.dirty = true;
+    }
+
+    /// Forgets every file under `dir` that isn't in `seen`, so deleted files don't pile up.
+    pub fn prune(&mut self, dir: &Path, seen: &HashSet<PathBuf>) {
+        let len = self.entries.len();
+        self.entries
+            .retain(|path, _| !path.starts_with(dir) || seen.contains(path));
+
+        if self.entries.len() < len {
+            tracing::debug!("Dropped {} stale cache entries", len - self.entries.len());
+            self.dirty = true;
+        }
+    }
+
+    /// Format of the file, `None` if it isn't an image.
+    pub fn format(&mut self, path: &Path) -> io::Result<Option<ImageFormat>> {
+        let entry = self.entry(path)?;
+        if entry.detected {
+            return Ok(entry.format);
+        }
+
+        let format = ImageFormat::detect(path)?;
+        entry.detected = true;
+        entry.format = format;
+        entry.dimensions = format.and_then(|_| magic::dimensions(path));
+        self.dirty = true;
+        Ok(format)
+    }
+
+    /// Width and height of the image, `None` if they can't be read from its header.
+    pub fn dimensions(&mut self, path: &Path) -> Option<(u32, u32)> {
+        self.format(path).ok()??;
+        self.entries.get(path)?.dimensions
+    }
+
+    pub fn content_hash(&mut self, path: &Path) -> io::Result<String> {
+        let entry = self.entry(path)?;
+        if let Some(hash) = &entry.content_hash {
+            return Ok(hash.clone());
+        }
+
+        let hash = hash::content_hash(path)?;
+        entry.content_hash = Some(hash.clone());
+        self.dirty = true;
+        Ok(hash)
+    }
+
+    /// Returns `None` for images that can't be decoded, e.g. SVGs.
+    pub fn perceptual_hash(&mut self, path: &Path) -> Option<u64> {
+        let entry = self.entry(path).ok()?;
+        if entry.perceptual_hash.is_some() {
+            return entry.perceptual_hash;
+        }
+
+        match hash::perceptual_hash(path) {
+            Ok(hash) => {
+                entry.perceptual_hash = Some(hash);
+                self.dirty = true;
+                Some(hash)
+            }
+            Err(e) => {
+                tracing::debug!("No perceptual hash for {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Whether the palette of the file was extracted before. It might be outdated.
+    pub fn has_palette(&self, path: &Path) -> bool {
+        self.entries
+            .get(path)
+            .is_some_and(|entry| entry.palette.is_some())
+    }
+
+    /// Colours extracted before, without extracting them if they weren't. They might be outdated.
+    pub fn cached_palette(&self, path: &Path) -> Option<&[Color]> {
+        self.entries.get(path)?.palette.as_deref()
+    }
+
+    /// Dominant colours of the image, darkest first. `None` for images that can't be decoded.
+    pub fn palette(&mut self, path: &Path) -> Option<Vec<Color>> {
+        let entry = self.entry(path).ok()?;
+        if entry.palette.is_some() {
+            return entry.palette.clone();
+        }
+
+        match palette::extract(path) {
+            Ok(palette) => {
+                entry.palette = Some(palette.clone());
+                self.dirty = true;
+                Some(palette)
+            }
+            Err(e) => {
+                tracing::debug!("No palette for {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
+
+impl Entry {
+    // Fills in whatever `other`, an entry for the same version of the file, has and this one lacks.
+    fn fill(&mut self, other: Self) {
+        if !self.detected && other.detected {
+            self.detected = true;
+            self.format = other.format;
+            self.dimensions = other.dimensions;
+        }
+        self.content_hash = self.content_hash.take().or(other.content_hash);
+        self.perceptual_hash = self.perceptual_hash.or(other.perceptual_hash);
+        self.palette = self.palette.take().or(other.palette);
+    }
+}
+
+// The version comes first, so it can be checked before trying to decode the rest in an older format.
+fn decode(bytes: &[u8]) -> Option<CacheFile> {
+    match bincode::decode_from_slice::<u32, _>(bytes, config::standard()) {
+        Ok((CACHE_VERSION, _)) => {}
+        Ok(_) => {
+            tracing::info!("Cache format changed, starting over");
+            return None;
+        }
+        Err(e) => {
+            tracing::warn!("Error reading cache, starting over: {e}");
+            return None;
+        }
+    }
+
+    bincode::decode_from_slice(bytes, config::standard())
+        .inspect_err(|e| tracing::warn!("Error reading cache, starting over: {e}"))
+        .ok()
+        .map(|(file, _)| file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_invalidation() {
+        let dir = env::temp_dir().join(format!("walrus-cache-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lake.png");
+        fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let mut cache = Cache::default();
+        assert_eq!(cache.format(&path).unwrap(), Some(ImageFormat::Png));
+        assert_eq!(
+            cache.entries[&path].format,
+            Some(ImageFormat::Png),
+            "format should be cached"
+        );
+
+        // A different size invalidates the entry even if the modification time didn't change.
+        fs::write(&path, "Photo by someone").unwrap();
+        assert_eq!(cache.format(&path).unwrap(), None);
+
+        cache.prune(&dir, &HashSet::new());
+        assert!(cache.entries.is_empty());
+
+        // A snapshot scanned elsewhere is merged back without losing what was computed meanwhile.
+        fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+        cache.format(&path).unwrap();
+        let mut snapshot = cache.snapshot();
+        snapshot.entry(&path).unwrap().content_hash = Some("abc".to_owned());
+        snapshot.set_wallpapers(&dir, vec![path.clone()]);
+        cache.entry(&path).unwrap().perceptual_hash = Some(42);
+        cache.merge(snapshot);
+        assert_eq!(cache.entries[&path].content_hash.as_deref(), Some("abc"));
+        assert_eq!(cache.entries[&path].perceptual_hash, Some(42));
+        assert_eq!(cache.wallpapers(&dir), Some([path.clone()].as_slice()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use std::fs;
+use std::io;
+use std::os::unix;
+use std::path::Path;
+use std::path::PathBuf;
+
+use walkdir::WalkDir;
+
+// Categories are hidden directories under the wallpaper path that mirror its layout with symlinks,
+// e.g. <wallpaper_path>/.favourites/nature/lake.png -> <wallpaper_path>/nature/lake.png
+
+pub const LIKE: &str = "like";
+pub const DISLIKE: &str = "dislike";
+// Hidden directories that version control, sync tools and file managers keep among the files.
+const TOOL_DIRS: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    ".cache",
+    ".thumbnails",
+    ".stfolder",
+    ".stversions",
+];
+
+pub fn dir(base: &Path, category: &str) -> PathBuf {
+    base.join(format!(".{category}"))
+}
+
+/// Whether a top-level directory of the wallpaper path with this name holds a category.
+pub fn is_category(name: &str) -> bool {
+    name.starts_with('.') && !is_tool_dir(name)
+}
+
+/// Whether this is a directory some other program keeps its data in, which never has wallpapers.
+pub fn is_tool_dir(name: &str) -> bool {
+    TOOL_DIRS.contains(&name) || name.starts_with(".Trash")
+}
+
+/// Returns the name and number of wallpapers of every category, sorted by name.
+pub fn list(base: &Path) -> io::Result<Vec<(String, usize)>> {
+    Ok(names(base)?
+        .into_iter()
+        .map(|name| {
+            let count = WalkDir::new(dir(base, &name))
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|e| !e.file_type().is_dir())
+                .count();
+            (name, count)
+        })
+        .collect())
+}
+
+// Names of every category, sorted, without counting their wallpapers.
+fn names(base: &Path) -> io::Result<Vec<String>> {
+    let mut names = fs::read_dir(base)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let name = name.to_str().filter(|name| is_category(name))?;
+            Some(name.strip_prefix('.')?.to_owned())
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+pub fn add(base: &Path, wallpaper: &Path, category: &str) -> io::Result<()> {
+    let dst = dir(base, category).join(relative(base, wallpaper)?);
+
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    tracing::debug!("Symlinking: {} <- {}", wallpaper.display(), dst.display());
+    unix::fs::symlink(wallpaper, &dst)
+}
+
+/// Adds the wallpaper to the dislike category, which scans skip. The file itself stays where it
+/// is, so removing it from the category brings it back.
+pub fn dislike(base: &Path, wallpaper: &Path) -> io::Result<()> {
+    match add(base, wallpaper, DISLIKE) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+pub fn remove(base: &Path, wallpaper: &Path, category: &str) -> io::Result<()> {
+    let category_dir = dir(base, category);
+    let link = category_dir.join(relative(base, wallpaper)?);
+
+    tracing::debug!("Removing symlink: {}", link.display());
+    fs::remove_file(&link)?;
+
+    // Prune the directories that only existed to mirror the wallpaper's location. remove_dir fails
+    // on the first directory that still has other entries, which ends the loop.
+    let mut parent = link.parent();
+    while let Some(dir) = parent
+        && dir != category_dir
+        && fs::remove_dir(dir).is_ok()
+    {
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+/// Returns the names of every category the wallpaper is in.
+pub fn categories_of(base: &Path, wallpaper: &Path) -> io::Result<Vec<String>> {
+    let rel = relative(base, wallpaper)?;
+    Ok(names(base)?
+        .into_iter()
+        .filter(|name| dir(base, name).join(&rel).symlink_metadata().is_ok())
+        .collect())
+}
+
+/// Returns the wallpapers a category's symlinks point to.
+pub fn wallpapers(base: &Path, category: &str) -> io::Result<Vec<PathBuf>> {
+    let category_dir = dir(base, category);
+    if !category_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No such category: {category}"),
+        ));
+    }
+
+    Ok(WalkDir::new(&category_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(walkdir::DirEntry::path_is_symlink)
+        .filter_map(|entry| {
+            let target = fs::read_link(entry.path()).ok()?;
+            // Symlinks made by walrus are absolute, but relative ones made by hand are resolved
+            // from the directory they are in.
+            let target = match entry.path().parent() {
+                Some(parent) if target.is_relative() => parent.join(target),
+                _ => target,
+            };
+            target.is_file().then_some(target)
+        })
+        .collect())
+}
+
+// The wallpaper's path relative to the wallpaper directory, falling back to the canonical paths in
+// case either of them goes through a symlink.
+fn relative(base: &Path, wallpaper: &Path) -> io::Result<PathBuf> {
+    if let Ok(rel) = wallpaper.strip_prefix(base) {
+        return Ok(rel.to_owned());
+    }
+
+    let canonical_base = fs::canonicalize(base)?;
+    fs::canonicalize(wallpaper)?
+        .strip_prefix(&canonical_base)
+        .map(Path::to_path_buf)
+        .map_err(|_| {
+            io::Error::other(format!(
+                "{} is not inside {}",
+                wallpaper.display(),
+                base.display()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    use walrus_core::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn test_dislike() {
+        let base = env::temp_dir().join(format!("walrus-category-{}", process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("nature")).unwrap();
+        let wallpaper = base.join("nature/lake.png");
+        fs::write(&wallpaper, "").unwrap();
+
+        fs::create_dir_all(base.join(".git")).unwrap();
+        dislike(&base, &wallpaper).unwrap();
+        assert_eq!(list(&base).unwrap(), [(DISLIKE.to_owned(), 1)]);
+        // Disliking twice is fine too.
+        dislike(&base, &wallpaper).unwrap();
+        assert!(wallpaper.is_file());
+        assert_eq!(wallpapers(&base, DISLIKE).unwrap(), [wallpaper.as_path()]);
+
+        remove(&base, &wallpaper, DISLIKE).unwrap();
+        assert!(wallpapers(&base, DISLIKE).unwrap().is_empty());
+        assert!(wallpaper.is_file());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_category_interval() {
+        let base = env::temp_dir().join(format!("walrus-category-interval-{}", process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("loops")).unwrap();
+        let animated = base.join("loops/waves.gif");
+        let still = base.join("lake.png");
+        fs::write(&animated, "").unwrap();
+        fs::write(&still, "").unwrap();
+        add(&base, &animated, "animated").unwrap();
+
+        let toml = format!(
+            r#"
+            [general]
+            interval = "5m"
+            wallpaper_path = "{}"
+
+            [intervals]
+            ".animated" = "15m"
+            ".animated/loops" = "1h"
+            "#,
+            base.display()
+        );
+        let config = toml::from_str::<Config>(&toml).unwrap();
+        let interval = |wallpaper: &Path| {
+            config
+                .interval_for(wallpaper, &categories_of(&base, wallpaper).unwrap())
+                .min
+        };
+        assert_eq!(interval(&animated), Duration::from_hours(1));
+        assert_eq!(interval(&still), Duration::from_mins(5));
+
+        let other = base.join("waves.gif");
+        fs::write(&other, "").unwrap();
+        add(&base, &other, "animated").unwrap();
+        assert_eq!(interval(&other), Duration::from_mins(15));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
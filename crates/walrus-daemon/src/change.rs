@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+use walrus_core::config::Config;
+use walrus_core::utils;
+use walrus_core::utils::Dirs;
+
+use crate::backend;
+use crate::backend::BackendError;
+use crate::backend::Job;
+use crate::magic;
+use crate::prescale;
+use crate::span;
+use crate::swww;
+use crate::transition::TransitionArgBuilder;
+
+/// Pre-scaled wallpapers and spanned slices, under the cache directory.
+pub const SCALED_DIR: &str = "scaled";
+
+/// Everything needed to put a wallpaper on screen, so it can be done on a worker thread. swww can
+/// take a while, or hang until it times out, and the daemon keeps serving commands meanwhile.
+#[derive(Debug)]
+pub struct Change {
+    pub config: Config,
+    pub wallpaper: PathBuf,
+    /// Goes on the outputs that don't match the wallpaper's orientation, see `split_outputs`.
+    pub alternative: Option<PathBuf>,
+    /// Shared by every output, so they all change the same way.
+    pub transition: TransitionArgBuilder,
+    /// The wallpaper on each named output before the change.
+    pub shown: BTreeMap<String, PathBuf>,
+    /// Whether swww-daemon answered last time.
+    pub swww_ready: bool,
+}
+
+/// How a `Change` went, sent back to the daemon.
+#[derive(Debug)]
+pub struct Changed {
+    pub wallpaper: PathBuf,
+    pub outcome: Outcome,
+    /// The wallpaper on each named output after the change.
+    pub shown: BTreeMap<String, PathBuf>,
+    pub swww_ready: bool,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Shown,
+    Failed(BackendError),
+    /// swww-daemon isn't running and couldn't be started, which isn't the wallpaper's fault.
+    NoSwww,
+}
+
+impl Change {
+    pub fn run(mut self) -> Changed {
+        self.swww_ready = ensure_swww(&self.config, self.swww_ready);
+        let outcome = if !self.swww_ready {
+            Outcome::NoSwww
+        } else if let Err(e) = self.set() {
+            self.swww_ready = false;
+            Outcome::Failed(e)
+        } else {
+            Outcome::Shown
+        };
+
+        Changed {
+            wallpaper: self.wallpaper,
+            outcome,
+            shown: self.shown,
+            swww_ready: self.swww_ready,
+        }
+    }
+
+    // Only the outcome for the wallpaper itself counts, not the alternative's.
+    fn set(&mut self) -> Result<(), BackendError> {
+        let wallpaper = self.wallpaper.clone();
+        if self.config.span()
+            && let Some(slices) = span_slices(&self.config, &wallpaper)
+        {
+            return self.span(&wallpaper, slices);
+        }
+
+        let Some((matching, other)) = split_outputs(&self.config, &wallpaper) else {
+            return self.display(&wallpaper, &[]);
+        };
+
+        let result = self.display(&wallpaper, &matching);
+        if let Some(alternative) = self.alternative.clone() {
+            tracing::info!(
+                "Setting wallpaper on {}: {}",
+                other.join(", "),
+                alternative.display()
+            );
+            if let Err(e) = self.display(&alternative, &other) {
+                tracing::error!("Error setting {}: {e}", alternative.display());
+            }
+        }
+        result
+    }
+
+    // Shows the wallpaper on the given outputs, or all of them if there are none, and remembers
+    // which output shows what.
+    fn display(&mut self, path: &Path, outputs: &[String]) -> Result<(), BackendError> {
+        self.show(path, outputs)?;
+
+        if outputs.is_empty() {
+            self.shown.clear();
+        }
+        let names = self
+            .config
+            .outputs()
+            .iter()
+            .filter_map(|o| o.name.clone())
+            .filter(|name| outputs.is_empty() || outputs.contains(name))
+            .collect::<Vec<_>>();
+        for name in names {
+            self.shown.insert(name, path.to_owned());
+        }
+        Ok(())
+    }
+
+    // With prescaling enabled every output gets a copy rendered at its own resolution.
+    fn show(&self, path: &Path, outputs: &[String]) -> Result<(), BackendError> {
+        let Some(sizes) = output_sizes(&self.config, path, outputs) else {
+            return self.run_swww(path, outputs);
+        };
+
+        sizes
+            .into_iter()
+            .map(|(size, names)| {
+                let scaled = prescaled(&self.config, path, size).unwrap_or_else(|e| {
+                    tracing::error!("Error scaling {}: {e}", path.display());
+                    path.to_owned()
+                });
+                self.run_swww(&scaled, &names)
+            })
+            .fold(Ok(()), Result::and)
+    }
+
+    // Sets a slice of the wallpaper on every output, so together they show the whole image. All
+    // slices are set at once.
+    fn span(&mut self, path: &Path, slices: Vec<(String, PathBuf)>) -> Result<(), BackendError> {
+        let args = self.transition.clone().build();
+        let timeout = Duration::from_secs(self.config.swww_timeout());
+        backend::retry(self.config.swww_retries(), || {
+            let jobs = slices
+                .iter()
+                .map(|(output, slice)| {
+                    Job::spawn(self.swww(&args, slice).args(["--outputs", output]))
+                })
+                .collect::<Vec<_>>();
+            // Waits for every slice, even after one failed, so none are left running.
+            jobs.into_iter()
+                .map(|job| job.and_then(|job| job.wait(timeout)).map(drop))
+                .fold(Ok(()), Result::and)
+        })?;
+
+        self.shown = slices
+            .into_iter()
+            .map(|(output, _)| (output, path.to_owned()))
+            .collect();
+        Ok(())
+    }
+
+    // Runs `swww img` for the given outputs, or all of them if there are none.
+    fn run_swww(&self, path: &Path, outputs: &[String]) -> Result<(), BackendError> {
+        let args = if outputs.is_empty() {
+            self.transition.clone().build()
+        } else {
+            self.transition
+                .clone()
+                .with_outputs(outputs.to_vec())
+                .build()
+        };
+        let timeout = Duration::from_secs(self.config.swww_timeout());
+        backend::retry(self.config.swww_retries(), || {
+            Job::spawn(&mut self.swww(&args, path))?
+                .wait(timeout)
+                .map(drop)
+        })
+    }
+
+    fn swww(&self, args: &[String], path: &Path) -> Command {
+        let mut command = Command::new(self.config.swww_path());
+        command.args(args).arg(path);
+        command
+    }
+}
+
+/// Checks that swww-daemon is running, starting it with the configured command if it isn't.
+/// Takes `ready`'s word for it, whether it answered last time.
+pub fn ensure_swww(config: &Config, ready: bool) -> bool {
+    if ready {
+        return true;
+    }
+
+    let swww_path = config.swww_path();
+    let timeout = Duration::from_secs(config.swww_timeout());
+    match swww::query(&swww_path, timeout) {
+        Ok(_) => true,
+        Err(e) => {
+            tracing::warn!("swww-daemon isn't responding: {e}");
+            config
+                .swww_daemon()
+                .is_some_and(|command| swww::start(&command, &swww_path, timeout))
+        }
+    }
+}
+
+/// With `match_aspect` on and both portrait and landscape outputs, the outputs matching the
+/// wallpaper's orientation and the others. `None` if every output gets the wallpaper.
+pub fn split_outputs(config: &Config, path: &Path) -> Option<(Vec<String>, Vec<String>)> {
+    let portrait = magic::dimensions(path)
+        .map(|(width, height)| height > width)
+        .filter(|_| config.match_aspect())?;
+
+    let names = |portrait: bool| {
+        config
+            .outputs()
+            .iter()
+            .filter(|o| o.is_portrait() == portrait)
+            .filter_map(|o| o.name.clone())
+            .collect::<Vec<_>>()
+    };
+    let (matching, other) = (names(portrait), names(!portrait));
+    (!matching.is_empty() && !other.is_empty()).then_some((matching, other))
+}
+
+// The outputs (all of them if there are none) grouped by size, for prescaling. Outputs of the same
+// size share a copy. `None` if the original should be shown instead.
+fn output_sizes(
+    config: &Config,
+    path: &Path,
+    outputs: &[String],
+) -> Option<BTreeMap<(u32, u32), Vec<String>>> {
+    if !config.prescale() || magic::dimensions(path).is_none() {
+        return None;
+    }
+
+    let targets = config
+        .outputs()
+        .iter()
+        .filter(|o| outputs.is_empty() || o.name.as_ref().is_some_and(|n| outputs.contains(n)))
+        .collect::<Vec<_>>();
+    // Outputs swww can't be pointed at separately all get the original.
+    if targets.is_empty() || targets.iter().any(|o| o.name.is_none()) {
+        return None;
+    }
+
+    let mut sizes = BTreeMap::<(u32, u32), Vec<String>>::new();
+    for output in targets {
+        sizes
+            .entry(output.screen_size())
+            .or_default()
+            .extend(output.name.clone());
+    }
+    Some(sizes)
+}
+
+fn prescaled(config: &Config, path: &Path, size: (u32, u32)) -> Result<PathBuf, Box<dyn Error>> {
+    let dimensions = magic::dimensions(path).ok_or("Unknown image size")?;
+    let dir = utils::get_dir(Dirs::Cache)?
+        .join(utils::APPNAME)
+        .join(SCALED_DIR);
+    let filter = config.filter();
+    let resize = config.resize();
+    let scaled = prescale::prescale(path, dimensions, size, &filter, &resize, &dir)?;
+
+    let max_size = config.prescale_max_size() * 1024 * 1024;
+    if let Err(e) = prescale::evict(&dir, max_size) {
+        tracing::warn!("Error evicting scaled wallpapers: {e}");
+    }
+    Ok(scaled)
+}
+
+// The wallpaper cut into a slice per output, see `Change::span`.
+fn span_slices(config: &Config, path: &Path) -> Option<Vec<(String, PathBuf)>> {
+    let outputs = config.outputs();
+    if outputs.len() < 2 || outputs.iter().any(|o| o.name.is_none()) {
+        tracing::debug!(
+            "Not spanning {}: needs multiple named outputs",
+            path.display()
+        );
+        return None;
+    }
+    let dimensions = magic::dimensions(path)?;
+
+    let dir = match utils::get_dir(Dirs::Cache) {
+        Ok(dir) => dir.join(utils::APPNAME).join(SCALED_DIR),
+        Err(e) => {
+            tracing::error!("Error getting cache directory: {e}");
+            return None;
+        }
+    };
+    let slices = match span::slices(path, dimensions, outputs, &config.filter(), &dir) {
+        Ok(slices) if !slices.is_empty() => slices,
+        Ok(_) => return None,
+        Err(e) => {
+            tracing::error!("Error slicing {}: {e}", path.display());
+            return None;
+        }
+    };
+    if let Err(e) = prescale::evict(&dir, config.prescale_max_size() * 1024 * 1024) {
+        tracing::warn!("Error evicting scaled wallpapers: {e}");
+    }
+    Some(slices)
+}
+
+/// Renders what setting `path` would need, so it's ready by the time it's set. Scaling a big image
+/// can take a second or more.
+pub fn prerender(config: &Config, path: &Path) {
+    if config.span() && span_slices(config, path).is_some() {
+        return;
+    }
+
+    let outputs = split_outputs(config, path)
+        .map(|(matching, _)| matching)
+        .unwrap_or_default();
+    for size in output_sizes(config, path, &outputs)
+        .unwrap_or_default()
+        .into_keys()
+    {
+        if let Err(e) = prescaled(config, path, size) {
+            tracing::debug!("Error pre-rendering {}: {e}", path.display());
+        }
+    }
+}
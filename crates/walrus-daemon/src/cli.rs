@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Flags on the command line, not state.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser)]
+#[command(name = "walrus-daemon", version = VERSION, about = "Daemon behind walrus, sets wallpapers with swww", long_about = None)]
+pub struct Args {
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Config file to use instead of $XDG_CONFIG_HOME/walrus/config.toml"
+    )]
+    pub config: Option<PathBuf>,
+    #[arg(long, help = "Shut down the running instance and take its place")]
+    pub replace: bool,
+    #[arg(
+        long,
+        help = "Log to the terminal in a readable format instead of to journald"
+    )]
+    pub foreground: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "Log level or filter, e.g. debug or walrus_daemon=trace"
+    )]
+    pub log_level: Option<String>,
+    #[arg(
+        long,
+        help = "Also log to stderr, e.g. for a service manager to collect"
+    )]
+    pub log_stderr: bool,
+    #[arg(long, help = "Don't reload the config when it changes")]
+    pub no_watch: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "WALRUS_SOCKET",
+        help = "IPC socket to use instead of $XDG_RUNTIME_DIR/walrus, for separate instances"
+    )]
+    pub socket: Option<PathBuf>,
+}
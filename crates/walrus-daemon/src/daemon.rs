@@ -1,154 +1,741 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Display;
-use std::fs;
-use std::os::unix;
+use std::fmt::Write;
+use std::mem;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+use std::sync::TryLockError;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use rand::Rng;
 use rand::SeedableRng;
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
-use walkdir::WalkDir;
+use walrus_core::commands::CategoryAction;
 use walrus_core::commands::Commands;
+use walrus_core::commands::TagAction;
 use walrus_core::config::Config;
+use walrus_core::config::Hook;
+use walrus_core::config::Interval;
+use walrus_core::config::Order;
 use walrus_core::config::Pos;
 use walrus_core::config::Resolution;
 use walrus_core::config::TransitionFlavour;
 use walrus_core::config::WaveSize;
 use walrus_core::ipc;
+use walrus_core::ipc::Request;
+use walrus_core::systemd;
+use walrus_core::utils;
+use walrus_core::utils::Dirs;
 
+use crate::cache::Cache;
+use crate::category;
+use crate::change;
+use crate::change::Change;
+use crate::change::Changed;
+use crate::change::Outcome;
+use crate::dedupe;
+use crate::hooks;
+use crate::logging;
+use crate::magic;
+use crate::palette;
+use crate::palette::Color;
+use crate::palette::Lab;
+use crate::power;
+use crate::power::Power;
+use crate::scan;
+use crate::scan::ScanOptions;
+use crate::schedule;
+use crate::schedule::SuspendWatch;
+use crate::state::State;
+use crate::swww;
+use crate::tags::Query;
+use crate::tags::TagStore;
+use crate::theme;
 use crate::transition::TransitionArgBuilder;
 
+// How many wallpapers in a row swww may fail on before walrus stops skipping them. At that point it's
+// more likely that swww itself is broken than the wallpapers.
+const MAX_FAILED_WALLPAPERS: usize = 3;
+// Rendered templates and the palette, under the cache directory. Kept apart so a template can't
+// overwrite the cache itself.
+const THEME_DIR: &str = "theme";
+// Changes closer than this to a boundary with `align` wait for the one after instead.
+const ALIGN_SLACK: Duration = Duration::from_secs(1);
+
+// What the main loop waits for.
+#[derive(Debug)]
+enum Event {
+    Command(Request),
+    Changed(Changed),
+    // Colours were extracted for every wallpaper in the queue.
+    Palettes,
+    // Wallpapers found on a worker thread to play. Without a category, they are all of them.
+    Play {
+        wallpapers: Vec<PathBuf>,
+        category: Option<String>,
+        reply: Option<Sender<String>>,
+    },
+    // Nothing is left to send commands.
+    Disconnected,
+}
+
+// Whether a wallpaper change is running on a worker thread.
+#[derive(Debug, PartialEq)]
+enum ChangeState {
+    Idle,
+    Running,
+    // Another change was asked for while it was running.
+    Superseded,
+}
+
 #[derive(Debug)]
 pub struct Daemon {
     pub config: Config,
+    // Given on the command line, the default config file is used otherwise.
+    config_path: Option<PathBuf>,
     pub paused: bool,
+    // When the wallpaper changes next, moved forward by the time spent paused.
+    deadline: Instant,
+    // When the wait for `deadline` started, moved forward the same way.
+    started: Instant,
+    paused_at: Instant,
+    // End of a timed pause.
+    resume_at: Option<Instant>,
+    suspend: SuspendWatch,
+    power: Power,
+    // Whether the battery running low paused playback, which resumes once it's charged again.
+    power_paused: bool,
     pub queue: Queue,
+    // Shared with the background rescan.
+    cache: Arc<Mutex<Cache>>,
+    rescan: Option<JoinHandle<Vec<PathBuf>>>,
+    // Looks for duplicates and replies by itself once it's done.
+    dedupe: Option<JoinHandle<()>>,
+    // Renders the next wallpaper in the queue ahead of time.
+    prerender: Option<JoinHandle<()>>,
+    // Extracts colours for `order = "colour"`.
+    palettes: Option<JoinHandle<()>>,
+    // Wallpapers swww failed to set, kept out of the queue even after rescanning.
+    bad: HashSet<PathBuf>,
+    // Category (or tag query) the queue is restricted to, if any.
+    category: Option<String>,
     rng: SmallRng,
+    // The wallpaper on each named output and the transition last used, for the state file.
+    shown: BTreeMap<String, PathBuf>,
+    transition: Option<TransitionFlavour>,
+    // Whether swww-daemon answered last time, checked again after swww fails.
+    swww_ready: bool,
+    change: ChangeState,
+    // Wallpapers swww failed to set since the last one it managed to.
+    failed: Vec<PathBuf>,
+    // Commands and the results of work done on other threads.
+    events: Sender<Event>,
+    events_rx: Receiver<Event>,
+    tags: Option<TagStore>,
 }
 
 impl Daemon {
-    pub fn new(config: Config) -> Self {
-        let directory = config.wallpaper_path();
+    pub fn new(config: Config, config_path: Option<PathBuf>) -> Self {
+        let cache = Cache::load();
+        let cached = cache
+            .wallpapers(&config.wallpaper_path())
+            .filter(|wallpapers| !wallpapers.is_empty())
+            .map(<[PathBuf]>::to_vec);
+        let cache = Arc::new(Mutex::new(cache));
+
+        // Start with the wallpapers found last time and catch up on changes in the background,
+        // walking a big library can take a long time.
+        let (wallpapers, rescan) = match cached {
+            Some(wallpapers) => (wallpapers, Some(spawn_rescan(&config, &cache))),
+            None => (scan_wallpapers(&config, &mut lock(&cache)), None),
+        };
+        let queue = Queue::new(wallpapers);
+        let tags = load_tags(&config);
+        let (events, events_rx) = mpsc::channel();
 
         tracing::debug!("Starting with Config: {}", config);
         Self {
             config,
+            config_path,
             paused: false,
-            queue: Queue::new(&directory),
+            deadline: Instant::now(),
+            started: Instant::now(),
+            paused_at: Instant::now(),
+            resume_at: None,
+            suspend: SuspendWatch::new(),
+            power: Power::default(),
+            power_paused: false,
+            queue,
+            cache,
+            rescan,
+            dedupe: None,
+            prerender: None,
+            palettes: None,
+            bad: HashSet::new(),
+            category: None,
             rng: SmallRng::from_os_rng(),
+            shown: BTreeMap::new(),
+            transition: None,
+            swww_ready: false,
+            change: ChangeState::Idle,
+            failed: Vec::new(),
+            events,
+            events_rx,
+            tags,
         }
     }
 
-    pub fn run(&mut self, rx: &Receiver<Commands>) {
-        // TODO: have different sorting options (enum and match)
-        if self.config.shuffle() {
-            self.queue.shuffle();
-        } else {
-            self.queue.sort();
-        }
-        tracing::debug!("{:#?}", self.queue);
+    pub fn run(&mut self, rx: Receiver<Request>) {
+        self.order_queue();
+        self.check_power();
 
-        // Set wallpaper initially.
-        if let Some(wallpaper) = self.queue.get_current() {
-            tracing::info!("Setting wallpaper: {}", wallpaper.display());
-            self.set_wallpaper(wallpaper.clone().as_path());
+        forward(rx, self.events.clone());
+
+        // Set wallpaper initially, unless swww is still showing one of ours.
+        if self.adopt_current() {
+            self.deadline = self.next_change();
+        } else {
+            self.set_current_wallpaper();
         }
 
-        let mut cont = true;
-        while cont {
-            let interval = self.config.interval();
-            let timeout = Duration::from_secs(interval);
+        // An event that was received while coalescing Next and Previous presses.
+        let mut pending = None;
+        loop {
+            let Request { command, reply } = match self.wait(pending.take()) {
+                Ok(Event::Command(request)) => request,
+                // Also every so often to check for a suspend, so only act once the time came.
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.tick();
+                    continue;
+                }
+                /*
+                 * Unsure when this can happen. One such case is if there is an instance of walrus
+                 * already running and another one is started.
+                 * Since file locking was later implemented, that should not happen.
+                 */
+                Ok(Event::Disconnected) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    tracing::error!("Timeout: channel disconnected");
+                    break;
+                }
+                Ok(event) => {
+                    self.finish_work(event);
+                    continue;
+                }
+            };
 
-            match rx.recv_timeout(timeout) {
-                Ok(Commands::Config) => unreachable!(),
-                Ok(Commands::Categorise { category }) => {
-                    self.create_category_symlink(self.queue.get_current().unwrap(), &category);
+            match command {
+                command @ (Commands::Config | Commands::Logs { .. }) => {
+                    tracing::warn!("Ignoring {command:?} command, only walrus handles it");
+                }
+                Commands::Categorise { category } => self.categorise(&category),
+                Commands::Category { action } => {
+                    tracing::debug!("Received Category {action:?} command");
+                    self.handle_category(action, reply);
+                }
+                Commands::Tag { action } => {
+                    tracing::debug!("Received Tag {action:?} command");
+                    self.handle_tag(action, reply);
+                }
+                Commands::Dedupe => {
+                    tracing::debug!("Received Dedupe command");
+                    self.dedupe(reply);
+                }
+                Commands::Dislike => {
+                    tracing::debug!("Received Dislike command");
+                    self.dislike();
+                }
+                Commands::Like => {
+                    tracing::debug!("Received Like command");
+                    self.categorise(category::LIKE);
                 }
-                Ok(Commands::Next) => {
+                Commands::Next => {
                     tracing::debug!("Received Next command");
-                    self.next_wallpaper();
+                    let (steps, next) = coalesce(1, &self.events_rx);
+                    pending = next;
+                    self.skip(steps);
                 }
-                Ok(Commands::Pause) => {
+                Commands::Pause { duration, until } => {
                     tracing::debug!("Received Pause command");
-                    self.pause();
+                    self.pause(duration.or(until));
                 }
-                Ok(Commands::Previous) => {
+                Commands::Previous => {
                     tracing::debug!("Received Previous command");
-                    self.previous_wallpaper();
+                    let (steps, next) = coalesce(-1, &self.events_rx);
+                    pending = next;
+                    self.skip(steps);
                 }
-                Ok(Commands::Resume) => {
+                Commands::Resume => {
                     tracing::debug!("Received Resume command");
                     self.resume();
                 }
+                Commands::Toggle => {
+                    tracing::debug!("Received Toggle command");
+                    if self.paused {
+                        self.resume();
+                    } else {
+                        self.pause(None);
+                    }
+                }
                 /*
                  * Reload command is automatically called from Config::watch(). It is called on
                  * every modification event, including file removal. In the case of file removal
                  * the watcher checks whether a new file can be found.
                  */
-                Ok(Commands::Reload) => {
+                Commands::Reload => {
                     tracing::debug!("Received Reload command");
                     self.reload_config();
                 }
-                Ok(Commands::Shutdown) => {
+                Commands::Shutdown => {
                     tracing::debug!("Received Shutdown command");
-                    cont = false;
+                    break;
                 }
-                Err(mpsc::RecvTimeoutError::Timeout) => {
-                    if !self.paused {
-                        tracing::debug!("Timeout: changing wallpapers...");
-                        self.next_wallpaper();
-                        continue;
-                    }
-                    tracing::debug!("Timeout: paused, not changing wallpapers");
+            }
+        }
+
+        systemd::notify("STOPPING=1");
+        // Keep palettes and hashes computed since the last scan. Doesn't wait for a rescan that is
+        // merging its results, it saves the cache itself.
+        if let Some(mut cache) = try_lock(&self.cache) {
+            cache.save();
+        } else {
+            tracing::warn!("Not saving the cache, a rescan is still using it");
+        }
+    }
+
+    // Waits for the next event, or until it's time to act, and catches up on what happened
+    // meanwhile.
+    fn wait(&mut self, pending: Option<Event>) -> Result<Event, mpsc::RecvTimeoutError> {
+        // Commands don't restart the interval, only changing the wallpaper does.
+        let event = match (pending, self.timeout()) {
+            (Some(event), _) => Ok(event),
+            (None, Some(timeout)) => self.events_rx.recv_timeout(timeout),
+            (None, None) => self
+                .events_rx
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        self.finish_rescan();
+        if let Some(slept) = self.suspend.check() {
+            self.woke_up(slept);
+        }
+        self.check_power();
+        event
+    }
+
+    // Takes in the result of work done on a worker thread.
+    fn finish_work(&mut self, event: Event) {
+        match event {
+            Event::Changed(changed) => self.finish_change(changed),
+            Event::Palettes => self.palettes_extracted(),
+            Event::Play {
+                wallpapers,
+                category,
+                reply,
+            } => respond(reply.as_ref(), self.play_found(wallpapers, category)),
+            Event::Command(_) | Event::Disconnected => {}
+        }
+    }
+
+    fn order_queue(&mut self) {
+        match self.config.order() {
+            Order::Shuffle => {
+                let weight = self.config.like_weight();
+                if weight > 1 {
+                    let base = self.config.wallpaper_path();
+                    let liked = category::wallpapers(&base, category::LIKE).unwrap_or_default();
+                    self.queue.repeat(&liked.into_iter().collect(), weight);
                 }
-                /*
-                 * Unsure when this can happen. One such case is if there is an instance of walrus
-                 * already running and another one is started.
-                 * Since file locking was later implemented, that should not happen.
-                 */
-                Err(mpsc::RecvTimeoutError::Disconnected) => {
-                    tracing::error!("Timeout: channel disconnected");
-                    cont = false;
+                self.queue.shuffle();
+            }
+            Order::Sorted => self.queue.sort(),
+            // Extracting colours takes a while the first time, so until it's done the queue is
+            // shuffled and sorted once they're there.
+            Order::Colour => {
+                let cache = lock(&self.cache);
+                if self.queue.queue.iter().all(|path| cache.has_palette(path)) {
+                    self.queue.sort_by_colour(&cache);
+                } else {
+                    drop(cache);
+                    self.queue.shuffle();
+                    self.extract_palettes();
                 }
             }
         }
+        tracing::debug!("{:#?}", self.queue);
     }
 
-    fn create_category_symlink(&self, src: &Path, category: &str) {
-        let base_path = self.config.wallpaper_path();
-        let dir = base_path.join(format!(".{category}"));
+    fn categorise(&self, category: &str) {
+        let Some(current) = self.queue.get_current() else {
+            tracing::error!("No current wallpaper to categorise");
+            return;
+        };
+
+        if let Err(e) = category::add(&self.config.wallpaper_path(), current, category) {
+            tracing::error!("Error adding {} to {category}: {e}", current.display());
+        }
+    }
 
-        fs::create_dir_all(&dir).expect("Failed to create directories for: {dir:?}");
+    // Hashing every wallpaper can take a while the first time, so the report is put together in
+    // the background. If the client gave up waiting by then, the hashes are still cached.
+    fn dedupe(&mut self, reply: Option<Sender<String>>) {
+        if self.dedupe.as_ref().is_some_and(|d| !d.is_finished()) {
+            respond(
+                reply.as_ref(),
+                "Still looking for duplicates, try again in a bit".into(),
+            );
+            return;
+        }
 
-        let rel = src
-            .strip_prefix(&base_path)
-            .expect("Wallpaper prefix does not match base path");
-        let dst = dir.join(rel);
+        let config = self.config.clone();
+        let cache = Arc::clone(&self.cache);
+        self.dedupe = Some(thread::spawn(move || {
+            respond(reply.as_ref(), dedupe_report(&config, &cache));
+        }));
+    }
 
-        if let Some(parent) = dst.parent()
-            && let Err(e) = fs::create_dir_all(parent)
-        {
-            tracing::error!("Error (mkdir {parent:?}): {e}");
+    fn dislike(&mut self) {
+        let Some(current) = self.queue.get_current().cloned() else {
+            tracing::error!("No current wallpaper to dislike");
+            return;
+        };
+
+        if let Err(e) = category::dislike(&self.config.wallpaper_path(), &current) {
+            tracing::error!("Error disliking {}: {e}", current.display());
             return;
         }
 
-        tracing::debug!("Symlinking: {} <- {}", src.display(), dst.display());
-        if let Err(e) = unix::fs::symlink(src, &dst) {
-            tracing::error!(
-                "Error creating symlink ({} <- {}): {e}",
-                src.display(),
-                dst.display()
+        tracing::info!("Disliked {}", current.display());
+        self.queue.remove(&current);
+        if self.queue.is_empty() {
+            tracing::error!("No wallpapers left in queue, shutting down");
+            let _ = ipc::send_command(Commands::Shutdown);
+            return;
+        }
+        self.set_current_wallpaper();
+    }
+
+    fn handle_category(&mut self, action: CategoryAction, reply: Option<Sender<String>>) {
+        let base = self.config.wallpaper_path();
+
+        let message = match action {
+            CategoryAction::List => match category::list(&base) {
+                Ok(categories) if categories.is_empty() => "No categories found".into(),
+                Ok(categories) => categories
+                    .iter()
+                    .map(|(name, count)| {
+                        let playing = if self.category.as_ref() == Some(name) {
+                            " (playing)"
+                        } else {
+                            ""
+                        };
+                        format!("{name}: {count} wallpapers{playing}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Err(e) => format!("Error listing categories: {e}"),
+            },
+            CategoryAction::Show => match self.queue.get_current() {
+                Some(current) => match category::categories_of(&base, current) {
+                    Ok(categories) if categories.is_empty() => {
+                        format!("{} is not in any category", current.display())
+                    }
+                    Ok(categories) => categories.join("\n"),
+                    Err(e) => format!("Error reading categories: {e}"),
+                },
+                None => "No wallpaper is set".into(),
+            },
+            CategoryAction::Remove { category } => match self.queue.get_current() {
+                Some(current) => match category::remove(&base, current, &category) {
+                    Ok(()) => format!("Removed {} from {category}", current.display()),
+                    Err(e) => format!("Error removing {} from {category}: {e}", current.display()),
+                },
+                None => "No wallpaper is set".into(),
+            },
+            CategoryAction::Play { category } => match category::wallpapers(&base, &category) {
+                Ok(wallpapers) if wallpapers.is_empty() => format!("Category {category} is empty"),
+                Ok(wallpapers) => {
+                    tracing::info!("Playing category {category}");
+                    self.play(wallpapers, Some(category.clone()));
+                    format!("Playing {} wallpapers from {category}", self.queue.len())
+                }
+                Err(e) => format!("Error playing {category}: {e}"),
+            },
+            CategoryAction::PlayAll => return self.play_all(reply),
+        };
+        respond(reply.as_ref(), message);
+    }
+
+    // Plays the wallpapers found by the last scan. If the wallpaper directory changed since, it's
+    // scanned on a worker thread that sends `Event::Play` once it's done.
+    fn play_all(&mut self, reply: Option<Sender<String>>) {
+        let cached = lock(&self.cache)
+            .wallpapers(&self.config.wallpaper_path())
+            .map(<[PathBuf]>::to_vec);
+        if let Some(wallpapers) = cached {
+            respond(reply.as_ref(), self.play_found(wallpapers, None));
+            return;
+        }
+
+        let config = self.config.clone();
+        let cache = Arc::clone(&self.cache);
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let wallpapers = rescan(&config, &cache);
+            let _ = events.send(Event::Play {
+                wallpapers,
+                category: None,
+                reply,
+            });
+        });
+    }
+
+    fn handle_tag(&mut self, action: TagAction, reply: Option<Sender<String>>) {
+        let base = self.config.wallpaper_path();
+        let current = self.queue.get_current().cloned();
+        let Some(store) = &mut self.tags else {
+            respond(
+                reply.as_ref(),
+                "Tags are disabled, set `enabled = true` under [tags] to use them".into(),
             );
+            return;
+        };
+
+        let message = match action {
+            TagAction::Add { tags } => match current {
+                Some(current) => match store.add(&current, &tags) {
+                    Ok(()) => saved(
+                        store,
+                        format!("Tagged {} with {}", current.display(), tags.join(", ")),
+                    ),
+                    Err(e) => format!("Error tagging {}: {e}", current.display()),
+                },
+                None => "No wallpaper is set".into(),
+            },
+            TagAction::Remove { tags } => match current {
+                Some(current) => match store.remove(&current, &tags) {
+                    Ok(()) => saved(
+                        store,
+                        format!("Removed {} from {}", tags.join(", "), current.display()),
+                    ),
+                    Err(e) => format!("Error untagging {}: {e}", current.display()),
+                },
+                None => "No wallpaper is set".into(),
+            },
+            TagAction::Show => match current {
+                Some(current) => match store.tags_of(&current) {
+                    Ok(tags) if tags.is_empty() => format!("{} has no tags", current.display()),
+                    Ok(tags) => tags.join("\n"),
+                    Err(e) => format!("Error reading tags: {e}"),
+                },
+                None => "No wallpaper is set".into(),
+            },
+            TagAction::List => {
+                let tags = store.list();
+                if tags.is_empty() {
+                    "No tags found".into()
+                } else {
+                    tags.iter()
+                        .map(|(tag, count)| format!("{tag}: {count} wallpapers"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+            }
+            TagAction::Play { query } => {
+                let store = store.clone();
+                return self.play_tagged(store, query.join(" "), reply);
+            }
+            TagAction::Import => match store.import(&base) {
+                Ok(count) => saved(
+                    store,
+                    format!("Imported tags for {count} categorised wallpapers"),
+                ),
+                Err(e) => format!("Error importing categories: {e}"),
+            },
+        };
+        respond(reply.as_ref(), message);
+    }
+
+    // Wallpapers that weren't hashed yet have to be for the query, so it's matched on a worker
+    // thread that sends `Event::Play` once it's done. Tags changed meanwhile aren't taken into
+    // account.
+    fn play_tagged(&self, store: TagStore, query: String, reply: Option<Sender<String>>) {
+        let parsed = match query.parse::<Query>() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                respond(reply.as_ref(), format!("Invalid query: {e}"));
+                return;
+            }
+        };
+
+        let config = self.config.clone();
+        let cache = Arc::clone(&self.cache);
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let wallpapers = store.query(&parsed, &hashed_wallpapers(&config, &cache));
+            let _ = events.send(Event::Play {
+                wallpapers,
+                category: Some(query),
+                reply,
+            });
+        });
+    }
+
+    // Takes in the result of the background rescan once it's done. Wallpapers that are gone leave the
+    // queue and new ones are put in where the order has them, the rest of the queue stays as it is.
+    fn finish_rescan(&mut self) {
+        if !self.rescan.as_ref().is_some_and(JoinHandle::is_finished) {
+            return;
+        }
+        let Some(rescan) = self.rescan.take() else {
+            return;
+        };
+
+        let Ok(mut wallpapers) = rescan.join() else {
+            tracing::error!("Rescanning wallpapers failed");
+            return;
+        };
+        // Playing a category or tag query doesn't depend on the scan.
+        if self.category.is_some() {
+            return;
+        }
+
+        wallpapers.retain(|wallpaper| !self.bad.contains(wallpaper));
+        let new = self.queue.keep_found(wallpapers);
+        if new.is_empty() {
+            return;
+        }
+
+        tracing::debug!("Rescan found {} new wallpapers", new.len());
+        match self.config.order() {
+            // Somewhere still to come before the queue wraps around, as often as `order_queue`
+            // would have put them in.
+            Order::Shuffle => {
+                let weight = self.config.like_weight();
+                let liked = if weight > 1 {
+                    let base = self.config.wallpaper_path();
+                    category::wallpapers(&base, category::LIKE).unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                for path in new {
+                    let copies = if liked.contains(&path) { weight } else { 1 };
+                    for _ in 0..copies {
+                        let start = if self.queue.is_empty() {
+                            0
+                        } else {
+                            self.queue.index + 1
+                        };
+                        let index = self.rng.random_range(start..=self.queue.len());
+                        self.queue.insert(index, path.clone());
+                    }
+                }
+            }
+            Order::Sorted => {
+                for path in new {
+                    let index = self.queue.queue.binary_search(&path).unwrap_or_else(|i| i);
+                    self.queue.insert(index, path);
+                }
+            }
+            // Sorted in by `palettes_extracted` once they have their colours.
+            Order::Colour => {
+                self.queue.queue.extend(new);
+                self.extract_palettes();
+            }
+        }
+    }
+
+    // Extracts the colours of every wallpaper in the queue that doesn't have them yet, on a worker
+    // thread that sends `Event::Palettes` once it's done.
+    fn extract_palettes(&mut self) {
+        if self.palettes.as_ref().is_some_and(|p| !p.is_finished()) {
+            return;
+        }
+
+        let wallpapers = self.queue.queue.iter().cloned().collect::<HashSet<_>>();
+        let cache = Arc::clone(&self.cache);
+        let events = self.events.clone();
+        self.palettes = Some(thread::spawn(move || {
+            let mut snapshot = lock(&cache).snapshot();
+            let missing = wallpapers
+                .iter()
+                .filter(|path| !snapshot.has_palette(path))
+                .collect::<Vec<_>>();
+            tracing::info!(
+                "Extracting colours from {} wallpapers, this only happens once",
+                missing.len()
+            );
+            for path in missing {
+                snapshot.palette(path);
+            }
+
+            let mut cache = lock(&cache);
+            cache.merge(snapshot);
+            cache.save();
+            drop(cache);
+            let _ = events.send(Event::Palettes);
+        }));
+    }
+
+    // Sorts the queue by colour now that they have been extracted, staying on the current
+    // wallpaper.
+    fn palettes_extracted(&mut self) {
+        if self.config.order() != Order::Colour {
+            return;
         }
+        let current = self.queue.get_current().cloned();
+        self.queue.sort_by_colour(&lock(&self.cache));
+        if let Some(current) = current {
+            self.queue.select(&current);
+        }
+    }
+
+    // Plays wallpapers found for `play_all`, or for a category or tag query.
+    fn play_found(&mut self, wallpapers: Vec<PathBuf>, category: Option<String>) -> String {
+        if let Some(category) = category {
+            if wallpapers.is_empty() {
+                return format!("No wallpapers match {category}");
+            }
+            tracing::info!("Playing wallpapers matching {category}");
+            self.play(wallpapers, Some(category.clone()));
+            format!(
+                "Playing {} wallpapers matching {category}",
+                self.queue.len()
+            )
+        } else if wallpapers.is_empty() {
+            "No wallpapers found".into()
+        } else {
+            tracing::info!("Playing all wallpapers");
+            self.play(wallpapers, None);
+            format!("Playing all {} wallpapers", self.queue.len())
+        }
+    }
+
+    // Swaps the queue for the given wallpapers and shows the first one.
+    fn play(&mut self, mut wallpapers: Vec<PathBuf>, category: Option<String>) {
+        wallpapers.retain(|wallpaper| !self.bad.contains(wallpaper));
+        self.queue = Queue::new(wallpapers);
+        self.category = category;
+        self.order_queue();
+        self.set_current_wallpaper();
     }
 
-    fn new_transition(&mut self) -> Vec<String> {
+    // Picks the transition for the next change, shared by every output it's set on.
+    fn new_transition(&mut self) -> TransitionArgBuilder {
         let resolution = self.config.resolution();
 
         let bezier = self.config.bezier();
@@ -164,6 +751,7 @@ impl Daemon {
         let flavour_rng = self.rng.random_range(0..flavours.len());
         let flavour = flavours.get(flavour_rng).unwrap();
 
+        self.transition = Some(flavour.clone());
         let angle = self.rng.random_range(0.0..360.0);
 
         let duration = match flavour {
@@ -183,8 +771,7 @@ impl Daemon {
             .with_step(step)
             .with_bezier(bezier);
 
-        // This is a potentially potentially confusing reassignment?
-        let builder = match flavour {
+        match flavour {
             TransitionFlavour::Wipe => builder.with_angle(angle),
             TransitionFlavour::Wave => {
                 let (width_min, width_max, height_min, height_max) = self.config.wave_size();
@@ -201,12 +788,11 @@ impl Daemon {
                 let y: f32 = self.rng.random_range(0.0..=1.0);
                 builder.with_pos(Pos { x, y })
             }
-        };
-
-        builder.build()
+            TransitionFlavour::Simple | TransitionFlavour::None => builder,
+        }
     }
 
-    fn advance_wallpaper(&mut self, advance_fn: fn(&mut Queue)) {
+    fn advance_wallpaper(&mut self, advance_fn: impl FnOnce(&mut Queue)) {
         advance_fn(&mut self.queue);
 
         if let Some(current) = self.queue.get_current()
@@ -216,13 +802,245 @@ impl Daemon {
             self.queue.cleanup_invalid_files();
         }
 
-        if let Some(wallpaper) = self.queue.get_current() {
-            let wallpaper = wallpaper.clone();
-            tracing::info!("Setting wallpaper: {}", wallpaper.display());
-            self.set_wallpaper(wallpaper.as_path());
-        } else {
+        if self.queue.is_empty() {
             tracing::error!("No valid path found in queue, shutting down");
             let _ = ipc::send_command(Commands::Shutdown);
+        } else {
+            self.set_current_wallpaper();
+        }
+    }
+
+    // Sets the current wallpaper on a worker thread, see `finish_change`. A change asked for while
+    // one is still running follows once it's done.
+    fn set_current_wallpaper(&mut self) {
+        // Also after failing, so a broken swww isn't retried in a busy loop.
+        self.deadline = self.next_change();
+        if self.paused {
+            self.paused_at = Instant::now();
+        }
+
+        if self.change != ChangeState::Idle {
+            self.change = ChangeState::Superseded;
+            return;
+        }
+        self.failed.clear();
+        self.start_change();
+    }
+
+    fn start_change(&mut self) {
+        let Some(wallpaper) = self.queue.get_current().cloned() else {
+            return;
+        };
+        tracing::info!("Setting wallpaper: {}", wallpaper.display());
+        self.run_hook(Hook::PreChange);
+
+        // Outputs that don't match the wallpaper's orientation get the next wallpaper in the queue
+        // that does.
+        let alternative = change::split_outputs(&self.config, &wallpaper).and_then(|_| {
+            let portrait =
+                magic::dimensions(&wallpaper).is_some_and(|(width, height)| height > width);
+            self.queue.find_next(|p| {
+                magic::dimensions(p).is_some_and(|(width, height)| (height > width) != portrait)
+            })
+        });
+        let change = Change {
+            config: self.config.clone(),
+            wallpaper,
+            alternative,
+            transition: self.new_transition(),
+            shown: self.shown.clone(),
+            swww_ready: self.swww_ready,
+        };
+
+        let events = self.events.clone();
+        self.change = ChangeState::Running;
+        thread::spawn(move || {
+            let _ = events.send(Event::Changed(change.run()));
+        });
+    }
+
+    // Moves on to the next wallpaper if swww failed to set this one. Wallpapers that failed are
+    // only dropped once another one succeeds, so a broken swww doesn't empty the queue.
+    fn finish_change(&mut self, changed: Changed) {
+        self.swww_ready = changed.swww_ready;
+        // The queue moved on while swww was busy.
+        let superseded =
+            mem::replace(&mut self.change, ChangeState::Idle) == ChangeState::Superseded;
+
+        let retry = match changed.outcome {
+            Outcome::Shown => {
+                self.shown = changed.shown;
+                self.save_state(&changed.wallpaper);
+                for path in mem::take(&mut self.failed) {
+                    tracing::warn!("Removing {} from queue", path.display());
+                    self.queue.remove(&path);
+                    self.bad.insert(path);
+                }
+                if !superseded {
+                    // The interval might differ for the wallpaper that ended up being shown.
+                    self.deadline = self.next_change();
+                    self.notify_status();
+                    self.apply_theme(&changed.wallpaper);
+                    self.run_hook(Hook::PostChange);
+                    self.prerender_next();
+                }
+                false
+            }
+            Outcome::Failed(e) => {
+                tracing::error!("Error setting {}: {e}", changed.wallpaper.display());
+                notify_error(&format!(
+                    "Error setting {}: {e}",
+                    changed.wallpaper.display()
+                ));
+                self.failed.push(changed.wallpaper);
+                if self.failed.len() >= MAX_FAILED_WALLPAPERS.min(self.queue.len()) {
+                    tracing::error!(
+                        "swww failed to set {} wallpapers in a row, is swww-daemon running?",
+                        self.failed.len()
+                    );
+                    self.failed.clear();
+                    false
+                } else {
+                    true
+                }
+            }
+            // Not the wallpapers' fault, so don't move on to the next one.
+            Outcome::NoSwww => {
+                tracing::error!("swww-daemon isn't running, not setting wallpapers");
+                notify_error("swww-daemon isn't running");
+                self.failed.clear();
+                false
+            }
+        };
+
+        if superseded {
+            self.failed.clear();
+            self.start_change();
+        } else if retry {
+            self.queue.next();
+            self.start_change();
+        }
+    }
+
+    // Picks up the wallpaper swww is showing if it's in the queue, e.g. after walrus was
+    // restarted, instead of replacing it for no reason. Pre-scaled copies and slices are traced
+    // back to the wallpaper they were made from through the state file.
+    fn adopt_current(&mut self) -> bool {
+        self.swww_ready = change::ensure_swww(&self.config, self.swww_ready);
+        if !self.swww_ready {
+            return false;
+        }
+        let timeout = Duration::from_secs(self.config.swww_timeout());
+        let showing = match swww::query(&self.config.swww_path(), timeout) {
+            Ok(showing) => showing,
+            Err(e) => {
+                tracing::warn!("Error asking swww what it's showing: {e}");
+                return false;
+            }
+        };
+
+        if let Some(wallpaper) = showing.values().find(|path| self.queue.select(path)) {
+            tracing::info!("swww is already showing {}", wallpaper.display());
+            self.shown = showing;
+            return true;
+        }
+
+        let Ok(dir) = utils::get_dir(Dirs::Cache) else {
+            return false;
+        };
+        let scaled = dir.join(utils::APPNAME).join(change::SCALED_DIR);
+        let state = utils::get_dir(Dirs::State)
+            .ok()
+            .and_then(|dir| State::load(&dir.join(utils::APPNAME)).ok());
+        match state {
+            Some(state)
+                if !showing.is_empty()
+                    && showing.values().all(|path| path.starts_with(&scaled))
+                    && self.queue.select(&state.wallpaper) =>
+            {
+                tracing::info!("swww is already showing {}", state.wallpaper.display());
+                self.shown = state.outputs;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Records what is on screen in $XDG_STATE_HOME/walrus, so other programs (lock screens,
+    // greeters) can find the current wallpaper without talking to the socket.
+    fn save_state(&self, wallpaper: &Path) {
+        let dir = match utils::get_dir(Dirs::State) {
+            Ok(dir) => dir.join(utils::APPNAME),
+            Err(e) => {
+                tracing::error!("Error getting state directory: {e}");
+                return;
+            }
+        };
+
+        let state = State {
+            wallpaper: wallpaper.to_owned(),
+            set_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            category: self.category.clone(),
+            transition: self.transition.as_ref().map(ToString::to_string),
+            outputs: self.shown.clone(),
+        };
+        if let Err(e) = state.save(&dir) {
+            tracing::error!("Error saving state: {e}");
+        }
+    }
+
+    // Renders the theme templates with the wallpaper's colours, pywal-style.
+    fn apply_theme(&self, wallpaper: &Path) {
+        if !self.config.theme_enabled() {
+            return;
+        }
+
+        let Some(palette) = self.palette(wallpaper) else {
+            tracing::warn!("No colour palette for {}", wallpaper.display());
+            return;
+        };
+        let dir = match utils::get_dir(Dirs::Cache) {
+            Ok(dir) => dir.join(utils::APPNAME).join(THEME_DIR),
+            Err(e) => {
+                tracing::error!("Error getting cache directory: {e}");
+                return;
+            }
+        };
+
+        let templates = self.config.theme_templates();
+        if let Err(e) = theme::generate(wallpaper, &palette, templates.as_deref(), &dir) {
+            tracing::error!("Error generating theme: {e}");
+            return;
+        }
+        if let Some(command) = self.config.theme_reload() {
+            theme::reload(&command);
+        }
+    }
+
+    // Gets the next wallpaper ready in the background when it has to be scaled or sliced first. Skips
+    // it while the last one is still at it, rapid skips would only pile up work.
+    fn prerender_next(&mut self) {
+        if !self.config.prescale() && !self.config.span() {
+            return;
+        }
+        if self.prerender.as_ref().is_some_and(|p| !p.is_finished()) {
+            return;
+        }
+        let Some(next) = self.queue.find_next(|_| true) else {
+            return;
+        };
+
+        let config = self.config.clone();
+        self.prerender = Some(thread::spawn(move || change::prerender(&config, &next)));
+    }
+
+    // Doesn't wait for the cache while it's busy, extracting the palette is quick enough.
+    fn palette(&self, wallpaper: &Path) -> Option<Vec<Color>> {
+        match try_lock(&self.cache) {
+            Some(mut cache) => cache.palette(wallpaper),
+            None => palette::extract(wallpaper).ok(),
         }
     }
 
@@ -230,28 +1048,218 @@ impl Daemon {
         self.advance_wallpaper(Queue::next);
     }
 
-    fn previous_wallpaper(&mut self) {
-        self.advance_wallpaper(Queue::previous);
+    // Moves through the queue by `steps`, backwards if negative, and only sets the wallpaper it
+    // ends up at.
+    fn skip(&mut self, steps: isize) {
+        if steps == 0 {
+            return;
+        }
+        self.advance_wallpaper(|queue| {
+            for _ in 0..steps.unsigned_abs() {
+                if steps > 0 {
+                    queue.next();
+                } else {
+                    queue.previous();
+                }
+            }
+        });
     }
 
-    // TODO: Play/Pause could also be a toggle instead and just flip self.paused.
-    fn pause(&mut self) {
-        self.paused = true;
+    // Pauses until resumed, or for `duration`.
+    fn pause(&mut self, duration: Option<Duration>) {
+        if !self.paused {
+            self.paused = true;
+            self.paused_at = Instant::now();
+        }
+        // Too far in the future to represent is the same as forever.
+        self.resume_at = duration.and_then(|duration| Instant::now().checked_add(duration));
+        if let Some(duration) = duration {
+            tracing::info!("Pausing for {}s", duration.as_secs());
+        }
+        self.power_paused = false;
+        self.notify_status();
+        self.run_hook(Hook::Pause);
     }
 
     fn resume(&mut self) {
+        // Carry on with what was left of the interval, or wait for the next multiple of it.
+        if self.paused {
+            if self.config.align() {
+                self.deadline = self.next_change();
+            } else {
+                self.deadline += self.paused_at.elapsed();
+                self.started += self.paused_at.elapsed();
+            }
+        }
         self.paused = false;
+        self.power_paused = false;
+        self.resume_at = None;
+        self.notify_status();
+        self.run_hook(Hook::Resume);
+    }
+
+    // How long to wait for commands before the wallpaper should change or a timed pause ends,
+    // cut short to check for a suspend. `None` while paused indefinitely.
+    fn timeout(&self) -> Option<Duration> {
+        let until = if self.paused {
+            match self.resume_at {
+                Some(at) => at,
+                // Keep checking whether the battery is charged again.
+                None if self.power_paused => return Some(schedule::CHECK_INTERVAL),
+                None => return None,
+            }
+        } else {
+            self.deadline
+        };
+        Some(
+            until
+                .saturating_duration_since(Instant::now())
+                .min(schedule::CHECK_INTERVAL),
+        )
+    }
+
+    fn tick(&mut self) {
+        let now = Instant::now();
+        if self.paused {
+            if self.resume_at.is_some_and(|at| at <= now) {
+                tracing::debug!("Timeout: pause is over, resuming");
+                self.resume();
+            }
+        } else if self.deadline <= now {
+            tracing::debug!("Timeout: changing wallpapers...");
+            self.next_wallpaper();
+        }
+    }
+
+    // When the current wallpaper should change if the interval started now.
+    fn next_change(&mut self) -> Instant {
+        self.started = Instant::now();
+        self.change_after(self.started)
+    }
+
+    // When the current wallpaper should change if the interval started at `start`, somewhere within
+    // its interval's range. With `align` that's the next time the clock shows a multiple of the
+    // shortest interval, e.g. on the hour.
+    fn change_after(&mut self, start: Instant) -> Instant {
+        let range = self.current_interval();
+        if !self.config.align() {
+            return start + self.rng.random_range(range.min..=range.max);
+        }
+        let interval = range.min;
+        let mut wait = schedule::until_boundary(schedule::local_now(), interval);
+        // Right after changing on a boundary the clocks might not agree on it having passed yet.
+        if wait < ALIGN_SLACK {
+            wait += interval;
+        }
+        Instant::now() + wait
+    }
+
+    fn current_interval(&self) -> Interval {
+        match self.queue.get_current() {
+            Some(wallpaper) => {
+                let base = self.config.wallpaper_path();
+                let categories = category::categories_of(&base, wallpaper).unwrap_or_default();
+                self.config.interval_for(wallpaper, &categories)
+            }
+            None => self.config.interval(),
+        }
+    }
+
+    // Moves the deadline when the interval settings changed from `before`, counting the time
+    // already waited.
+    fn reschedule(&mut self, before: (Interval, bool)) {
+        if (self.current_interval(), self.config.align()) == before {
+            return;
+        }
+        tracing::debug!("Interval changed, rescheduling the next change");
+        self.deadline = self.change_after(self.started);
+    }
+
+    // Switches to or from the [power.battery] settings, and pauses while the battery is low.
+    fn check_power(&mut self) {
+        let before = (self.current_interval(), self.config.align());
+        let power = power::read(&self.config.power_sysfs());
+        if power.on_battery != self.power.on_battery {
+            if power.on_battery {
+                tracing::info!("Running on battery");
+            } else {
+                tracing::info!("Running on external power");
+            }
+        }
+        self.config.set_on_battery(power.on_battery);
+        self.reschedule(before);
+
+        let threshold = self.config.battery_pause_below();
+        let is_low = |power: Power| {
+            power.on_battery && power.capacity.is_some_and(|capacity| capacity < threshold)
+        };
+        let (was_low, low) = (is_low(self.power), is_low(power));
+        self.power = power;
+        // Only when it runs low, so resuming by hand sticks.
+        if low && !was_low && !self.paused {
+            tracing::info!("Battery below {threshold}%, pausing");
+            self.pause(None);
+            self.power_paused = true;
+        } else if !low && self.power_paused {
+            tracing::info!("Battery no longer low, resuming");
+            self.resume();
+        }
+    }
+
+    // The deadlines are on the monotonic clock, which stood still while the system slept.
+    fn woke_up(&mut self, slept: Duration) {
+        tracing::info!("Woke up after a suspend of {}s", slept.as_secs());
+        let now = Instant::now();
+        // Timed pauses are meant in real time.
+        self.resume_at = self
+            .resume_at
+            .map(|at| at.checked_sub(slept).unwrap_or(now));
+        if self.paused {
+            return;
+        }
+        if self.config.change_after_suspend() {
+            self.deadline = now;
+        } else if self.config.align() {
+            self.deadline = self.next_change();
+        }
     }
 
-    fn set_wallpaper(&mut self, path: &Path) {
-        let args = self.new_transition();
+    // Shown by `systemctl --user status` when running as a systemd service.
+    fn notify_status(&self) {
+        let wallpaper = self.queue.get_current().and_then(|path| path.file_name());
+        let status = match (wallpaper, self.paused) {
+            (Some(name), true) => format!("Paused on {}", name.display()),
+            (Some(name), false) => format!("Showing {}", name.display()),
+            (None, _) => "No wallpaper".into(),
+        };
+        systemd::notify(&format!("STATUS={status}"));
+    }
 
-        let _ = Command::new(self.config.swww_path())
-            .args(args)
-            .arg(path)
-            .spawn()
-            .expect("Error spawning sww process")
-            .wait();
+    // Hooks get the current wallpaper and where it's at in the environment.
+    fn run_hook(&self, hook: Hook) {
+        let Some(command) = self.config.hook(hook) else {
+            return;
+        };
+
+        let outputs = self
+            .config
+            .outputs()
+            .iter()
+            .filter_map(|o| o.name.as_deref())
+            .collect::<Vec<_>>();
+        let wallpaper = self
+            .queue
+            .get_current()
+            .map(|path| path.display().to_string());
+        let env = vec![
+            ("WALRUS_WALLPAPER", wallpaper.unwrap_or_default()),
+            ("WALRUS_OUTPUT", outputs.join(",")),
+            ("WALRUS_CATEGORY", self.category.clone().unwrap_or_default()),
+            ("WALRUS_INDEX", self.queue.index.to_string()),
+        ];
+
+        let timeout = Duration::from_secs(self.config.hook_timeout());
+        hooks::run(hook, command, env, timeout);
     }
 
     // WARN:
@@ -261,12 +1269,21 @@ impl Daemon {
     // differently so the debug information depends on how the file is edited.
     fn reload_config(&mut self) {
         tracing::info!("Reloading config...");
-        let config = Config::new().unwrap_or_else(|e| {
+        let before = (self.current_interval(), self.config.align());
+        let config = match &self.config_path {
+            Some(path) => Config::from_file(path),
+            None => Config::new(),
+        };
+        self.config = config.unwrap_or_else(|e| {
             tracing::error!("Error in config: {e}");
             tracing::warn!("Falling back to default config...");
             Config::default()
         });
-        self.config = config;
+        logging::apply(&self.config);
+        self.config.set_on_battery(self.power.on_battery);
+        self.reschedule(before);
+        self.tags = load_tags(&self.config);
+        self.run_hook(Hook::Reload);
     }
 }
 
@@ -284,25 +1301,8 @@ pub struct Queue {
 }
 
 impl Queue {
-    fn new(dir: &Path) -> Self {
-        Self {
-            queue: WalkDir::new(dir)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|entry| {
-                    !entry
-                        .path()
-                        .components()
-                        .nth(dir.components().count())
-                        .and_then(|c| c.as_os_str().to_str())
-                        .is_some_and(|s| s == ".like" || s == ".dislike")
-                })
-                .filter(|entry| entry.path().is_file())
-                .map(|entry| entry.path().to_owned())
-                .collect::<Vec<_>>(),
-            index: 0,
-        }
+    fn new(queue: Vec<PathBuf>) -> Self {
+        Self { queue, index: 0 }
     }
 
     fn shuffle(&mut self) {
@@ -314,12 +1314,104 @@ impl Queue {
 
     fn sort(&mut self) {
         self.queue.sort();
+        self.queue.dedup();
+    }
+
+    // Only uses colours that were extracted before, see `Daemon::extract_palettes`. Wallpapers
+    // without colours, e.g. ones that can't be decoded, go at the end.
+    fn sort_by_colour(&mut self, cache: &Cache) {
+        self.sort();
+        let mut coloured = Vec::new();
+        let mut uncoloured = Vec::new();
+        for path in self.queue.drain(..) {
+            match cache.cached_palette(&path).and_then(Lab::average) {
+                Some(lab) => coloured.push((path, lab)),
+                None => uncoloured.push(path),
+            }
+        }
+
+        self.queue = palette::colour_order(coloured);
+        self.queue.extend(uncoloured);
+        self.index = 0;
+    }
+
+    // Makes each wallpaper in `paths` appear `times` times in the queue, which is meant to be
+    // shuffled afterwards. Running it again doesn't stack up more copies.
+    fn repeat(&mut self, paths: &HashSet<PathBuf>, times: u32) {
+        self.sort();
+        let copies = self
+            .queue
+            .iter()
+            .filter(|path| paths.contains(*path))
+            .flat_map(|path| (1..times).map(move |_| path.clone()))
+            .collect::<Vec<_>>();
+        self.queue.extend(copies);
+    }
+
+    // Removes every occurrence of the path, keeping the index on the wallpaper that followed it.
+    fn remove(&mut self, path: &Path) {
+        let removed_before = self.queue[..self.index.min(self.queue.len())]
+            .iter()
+            .filter(|p| *p == path)
+            .count();
+        self.queue.retain(|p| p != path);
+        self.index -= removed_before;
+
+        if self.index >= self.queue.len() {
+            self.index = 0;
+        }
+    }
+
+    // Removes the wallpapers that weren't found again, keeping the index on the current one (or the
+    // one that followed it). Returns the found wallpapers that aren't in the queue yet.
+    fn keep_found(&mut self, found: Vec<PathBuf>) -> Vec<PathBuf> {
+        let found_set = found.iter().collect::<HashSet<_>>();
+        let gone = self
+            .queue
+            .iter()
+            .filter(|path| !found_set.contains(path))
+            .cloned()
+            .collect::<HashSet<_>>();
+        for path in &gone {
+            self.remove(path);
+        }
+
+        let known = self.queue.iter().cloned().collect::<HashSet<_>>();
+        found
+            .into_iter()
+            .filter(|path| !known.contains(path))
+            .collect()
+    }
+
+    // Puts a wallpaper in at `index`, keeping the current one.
+    fn insert(&mut self, index: usize, path: PathBuf) {
+        self.queue.insert(index, path);
+        if index <= self.index && self.queue.len() > 1 {
+            self.index += 1;
+        }
+    }
+
+    // Makes `path` the current wallpaper, returns whether it's in the queue.
+    fn select(&mut self, path: &Path) -> bool {
+        if let Some(index) = self.queue.iter().position(|p| p == path) {
+            self.index = index;
+            return true;
+        }
+        false
     }
 
     fn get_current(&self) -> Option<&PathBuf> {
         self.queue.get(self.index)
     }
 
+    // The first wallpaper after the current one (wrapping around) that matches the predicate.
+    fn find_next<P: Fn(&Path) -> bool>(&self, predicate: P) -> Option<PathBuf> {
+        (1..self.queue.len())
+            .map(|offset| &self.queue[(self.index + offset) % self.queue.len()])
+            .find(|path| predicate(path))
+            .cloned()
+    }
+
     fn next(&mut self) {
         if !self.queue.is_empty() {
             self.index = (self.index + 1) % self.queue.len();
@@ -346,6 +1438,10 @@ impl Queue {
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
 }
 
 impl Display for Queue {
@@ -357,6 +1453,184 @@ impl Display for Queue {
     }
 }
 
+// Collapses the Next and Previous presses that are already waiting into a single move, so rapid
+// presses only set the wallpaper they end up at. Returns how far to move and the first other
+// command, which still has to be handled.
+fn coalesce(first: isize, events: &Receiver<Event>) -> (isize, Option<Event>) {
+    let mut steps = first;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            Event::Command(Request {
+                command: Commands::Next,
+                ..
+            }) => steps += 1,
+            Event::Command(Request {
+                command: Commands::Previous,
+                ..
+            }) => steps -= 1,
+            event => return (steps, Some(event)),
+        }
+    }
+    (steps, None)
+}
+
+// Passes commands on to the main loop, where they come in along with results from worker threads.
+fn forward(rx: Receiver<Request>, events: Sender<Event>) {
+    thread::spawn(move || {
+        for request in rx {
+            if events.send(Event::Command(request)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(Event::Disconnected);
+    });
+}
+
+// Answers a request, if it came with a way to. The client may have given up waiting already.
+fn respond(reply: Option<&Sender<String>>, message: String) {
+    if let Some(reply) = reply {
+        let _ = reply.send(message);
+    }
+}
+
+// Errors setting wallpapers show up in `systemctl --user status` until the next change.
+fn notify_error(error: &str) {
+    let line = error.lines().next().unwrap_or_default();
+    systemd::notify(&format!("STATUS={line}"));
+}
+
+// Scans the wallpaper directory, dropping duplicates if enabled. The result is cached for the next
+// start.
+fn scan_wallpapers(config: &Config, cache: &mut Cache) -> Vec<PathBuf> {
+    let start = Instant::now();
+    let dir = config.wallpaper_path();
+    let mut wallpapers = scan::scan(&dir, &ScanOptions::from_config(config), cache);
+    if config.dedupe() {
+        wallpapers = dedupe::filter(wallpapers, cache, config.dedupe_threshold());
+    }
+    tracing::debug!(
+        "Found {} wallpapers in {:.2?}",
+        wallpapers.len(),
+        start.elapsed()
+    );
+
+    cache.set_wallpapers(&dir, wallpapers.clone());
+    cache.save();
+    wallpapers
+}
+
+fn spawn_rescan(config: &Config, cache: &Arc<Mutex<Cache>>) -> JoinHandle<Vec<PathBuf>> {
+    let config = config.clone();
+    let cache = Arc::clone(cache);
+    thread::spawn(move || rescan(&config, &cache))
+}
+
+// Scans into a snapshot, so the cache is only locked to take it and merge it back. For worker
+// threads.
+fn rescan(config: &Config, cache: &Mutex<Cache>) -> Vec<PathBuf> {
+    let mut snapshot = lock(cache).snapshot();
+    let wallpapers = scan_wallpapers(config, &mut snapshot);
+    let mut cache = lock(cache);
+    cache.merge(snapshot);
+    cache.save();
+    wallpapers
+}
+
+// Every wallpaper with its content hash, for tag queries. Like `rescan`, works on a snapshot of the
+// cache.
+fn hashed_wallpapers(config: &Config, cache: &Mutex<Cache>) -> Vec<(PathBuf, String)> {
+    let mut snapshot = lock(cache).snapshot();
+    let wallpapers = scan::scan(
+        &config.wallpaper_path(),
+        &ScanOptions::from_config(config),
+        &mut snapshot,
+    );
+    let hashed = wallpapers
+        .into_iter()
+        .filter_map(|path| match snapshot.content_hash(&path) {
+            Ok(hash) => Some((path, hash)),
+            Err(e) => {
+                tracing::warn!("Error hashing {}: {e}", path.display());
+                None
+            }
+        })
+        .collect();
+    let mut cache = lock(cache);
+    cache.merge(snapshot);
+    cache.save();
+    hashed
+}
+
+// Like `scan_wallpapers`, works on a snapshot of the cache and only locks it to merge it back.
+fn dedupe_report(config: &Config, cache: &Mutex<Cache>) -> String {
+    let mut snapshot = lock(cache).snapshot();
+    let wallpapers = scan::scan(
+        &config.wallpaper_path(),
+        &ScanOptions::from_config(config),
+        &mut snapshot,
+    );
+    let groups = dedupe::find(&wallpapers, &mut snapshot, config.dedupe_threshold());
+    let mut cache = lock(cache);
+    cache.merge(snapshot);
+    cache.save();
+    drop(cache);
+
+    if groups.is_empty() {
+        return "No duplicates found".into();
+    }
+
+    let mut report = String::new();
+    for (i, group) in groups.iter().enumerate() {
+        let kind = if group.exact { "identical" } else { "similar" };
+        let _ = writeln!(report, "Group {} ({kind}):", i + 1);
+        for (j, wallpaper) in group.wallpapers.iter().enumerate() {
+            let keep = if j == 0 { " (best)" } else { "" };
+            let _ = writeln!(report, "  {}{keep}", wallpaper.display());
+        }
+    }
+
+    let duplicates = groups.iter().map(|g| g.wallpapers.len() - 1).sum::<usize>();
+    let _ = write!(report, "{duplicates} duplicates in {} groups", groups.len());
+    report
+}
+
+// A panic while holding the lock leaves the cache usable, at worst with a few entries missing.
+fn lock(cache: &Mutex<Cache>) -> MutexGuard<'_, Cache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Like `lock`, but gives up if the cache is busy, e.g. with a rescan merging its results.
+fn try_lock(cache: &Mutex<Cache>) -> Option<MutexGuard<'_, Cache>> {
+    match cache.try_lock() {
+        Ok(cache) => Some(cache),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// Saves the tags before replying, unless that fails.
+fn saved(store: &TagStore, reply: String) -> String {
+    match store.save() {
+        Ok(()) => reply,
+        Err(e) => format!("Error saving tags: {e}"),
+    }
+}
+
+fn load_tags(config: &Config) -> Option<TagStore> {
+    if !config.tags_enabled() {
+        return None;
+    }
+
+    let Some(path) = config.tags_path() else {
+        tracing::error!("Could not find a location for the tag store");
+        return None;
+    };
+
+    TagStore::load(path)
+        .inspect_err(|e| tracing::error!("Error loading tags: {e}"))
+        .ok()
+}
+
 pub fn normalize_duration(base_duration: f64, res: Resolution, angle_degrees: f32) -> f64 {
     let width = f64::from(res.width);
     let height = f64::from(res.height);
@@ -0,0 +1,131 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cache::Cache;
+use crate::hash;
+
+/// Wallpapers that are copies of each other. The first one is the one worth keeping: the biggest
+/// image, then the biggest file.
+#[derive(Debug)]
+pub struct Group {
+    pub wallpapers: Vec<PathBuf>,
+    // Whether every wallpaper in the group has the exact same contents.
+    pub exact: bool,
+}
+
+/// Groups exact duplicates and images whose perceptual hashes differ in at most `threshold` bits.
+pub fn find(wallpapers: &[PathBuf], cache: &mut Cache, threshold: u32) -> Vec<Group> {
+    let mut sets = DisjointSets::new(wallpapers.len());
+
+    let content_hashes = wallpapers
+        .iter()
+        .map(|path| cache.content_hash(path).ok())
+        .collect::<Vec<_>>();
+    let mut first_with_hash = HashMap::new();
+    for (i, content_hash) in content_hashes.iter().enumerate() {
+        if let Some(content_hash) = content_hash {
+            let first = *first_with_hash.entry(content_hash).or_insert(i);
+            sets.union(first, i);
+        }
+    }
+
+    // Exact duplicates are already grouped, so only one of them needs comparing.
+    let perceptual_hashes = wallpapers
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| sets.find(i) == i)
+        .filter_map(|(i, path)| Some((i, cache.perceptual_hash(path)?)))
+        .collect::<Vec<_>>();
+    for (n, &(i, a)) in perceptual_hashes.iter().enumerate() {
+        for &(j, b) in &perceptual_hashes[n + 1..] {
+            if hash::distance(a, b) <= threshold {
+                sets.union(i, j);
+            }
+        }
+    }
+
+    let mut groups = HashMap::<usize, Vec<usize>>::new();
+    for i in 0..wallpapers.len() {
+        groups.entry(sets.find(i)).or_default().push(i);
+    }
+
+    let mut groups = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            members.sort_by_cached_key(|&i| {
+                let path = &wallpapers[i];
+                let pixels = cache
+                    .dimensions(path)
+                    .map_or(0, |(w, h)| u64::from(w) * u64::from(h));
+                let size = fs::metadata(path).map_or(0, |m| m.len());
+                (Reverse(pixels), Reverse(size), path.clone())
+            });
+
+            let exact = members
+                .iter()
+                .map(|&i| &content_hashes[i])
+                .collect::<HashSet<_>>()
+                .len()
+                == 1;
+
+            Group {
+                wallpapers: members.into_iter().map(|i| wallpapers[i].clone()).collect(),
+                exact,
+            }
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| a.wallpapers.cmp(&b.wallpapers));
+    groups
+}
+
+/// Drops all but the best wallpaper of every group of duplicates, keeping the order otherwise.
+pub fn filter(wallpapers: Vec<PathBuf>, cache: &mut Cache, threshold: u32) -> Vec<PathBuf> {
+    let duplicates = find(&wallpapers, cache, threshold)
+        .into_iter()
+        .flat_map(|group| group.wallpapers.into_iter().skip(1))
+        .collect::<HashSet<_>>();
+
+    if !duplicates.is_empty() {
+        tracing::info!("Skipping {} duplicate wallpapers", duplicates.len());
+    }
+    wallpapers
+        .into_iter()
+        .filter(|path| !duplicates.contains(path))
+        .collect()
+}
+
+// Union-find over wallpaper indices.
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // Point everything on the way straight at the root so later lookups are quick.
+        let mut i = i;
+        while self.parents[i] != root {
+            i = std::mem::replace(&mut self.parents[i], root);
+        }
+        root
+    }
+
+    // The smaller index becomes the root, which keeps the first of exact duplicates as the root.
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::Write;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use image::ImageReader;
+use image::ImageResult;
+use image::imageops::FilterType;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Hex encoded SHA-256 of the file's contents. Identifies an image regardless of its name or
+/// location.
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+/// 64-bit difference hash of the image. Resized or re-encoded copies of an image get hashes that
+/// only differ in a few bits, see `distance`.
+pub fn perceptual_hash(path: &Path) -> ImageResult<u64> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    // One column more than bits per row, since every bit compares two neighbouring pixels.
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = pixels.get_pixel(x, y)[0] < pixels.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+    Ok(hash)
+}
+
+/// Number of bits two perceptual hashes differ in.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,87 @@
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use walrus_core::config::Hook;
+
+// How often a running hook is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the hook's command with `sh -c` in the background, so a slow hook never holds up walrus.
+/// The command is killed if it's still running after `timeout`. The returned handle tells whether
+/// it succeeded, but doesn't have to be waited on.
+pub fn run(
+    hook: Hook,
+    command: String,
+    env: Vec<(&'static str, String)>,
+    timeout: Duration,
+) -> JoinHandle<bool> {
+    thread::spawn(move || {
+        tracing::debug!("Running {hook} hook: {command}");
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .envs(env)
+            .stdin(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::error!("Error running {hook} hook: {e}");
+                return false;
+            }
+        };
+
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => return true,
+                Ok(Some(status)) => {
+                    tracing::error!("{hook} hook failed ({status}): {command}");
+                    return false;
+                }
+                Ok(None) if start.elapsed() >= timeout => {
+                    tracing::error!("{hook} hook timed out after {timeout:?}, killing it");
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return false;
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    tracing::error!("Error waiting for {hook} hook: {e}");
+                    return false;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks() {
+        let env = vec![("WALRUS_INDEX", "3".to_owned())];
+        let hook = run(
+            Hook::PostChange,
+            r#"test "$WALRUS_INDEX" = 3"#.into(),
+            env,
+            Duration::from_secs(5),
+        );
+        assert!(hook.join().unwrap());
+
+        let start = Instant::now();
+        let hook = run(
+            Hook::PreChange,
+            "sleep 10".into(),
+            Vec::new(),
+            Duration::from_millis(100),
+        );
+        assert!(!hook.join().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,233 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::PoisonError;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+use tracing_subscriber::layer::Layered;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+use walrus_core::config::Config;
+use walrus_core::utils;
+
+use crate::cli::Args;
+
+// Used until the config is loaded.
+const INITIAL_LEVEL: &str = "info";
+// How often old log files are looked for, a new one is started every day.
+const PRUNE_INTERVAL: Duration = Duration::from_hours(1);
+const DAY: Duration = Duration::from_hours(24);
+
+type FileLayer = Box<dyn Layer<Registry> + Send + Sync>;
+type FileSubscriber = Layered<reload::Layer<FileLayer, Registry>, Registry>;
+
+// Handles to change logging after it started, when the config is (re)loaded.
+struct Logging {
+    dir: PathBuf,
+    file: reload::Handle<FileLayer, Registry>,
+    json: AtomicBool,
+    level: reload::Handle<EnvFilter, FileSubscriber>,
+    // Set by --log-level, which takes precedence over the config.
+    fixed_level: bool,
+    // Maximum number of files and age in days, 0 being unlimited.
+    retention: Mutex<(usize, u64)>,
+}
+
+static LOGGING: OnceLock<Logging> = OnceLock::new();
+
+/// Starts logging to a daily file in `dir`, and to journald if it's available. Pass the config to
+/// `apply` once it's loaded.
+pub fn init(dir: PathBuf, args: &Args) {
+    let fixed_level = args.log_level.is_some();
+    let level = args.log_level.as_deref().unwrap_or(INITIAL_LEVEL);
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|e| {
+        eprintln!("Invalid log level {level:?}: {e}");
+        process::exit(2)
+    });
+
+    let (file_layer, file) = reload::Layer::new(file_layer(&dir, false));
+    let (filter, level) = reload::Layer::new(filter);
+    let stderr_layer = (args.foreground || args.log_stderr).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(args.foreground)
+    });
+    // Some systems might not have journald or connecting to the socket could fail for whatever
+    // reason. If it does fail this will return None and the layer will just do nothing.
+    // https://docs.rs/tracing-subscriber/0.3.20/tracing_subscriber/layer/index.html#runtime-configuration-with-layers
+    // In the foreground the terminal takes its place, journald would only duplicate it.
+    let journald_layer = tracing_journald::layer().ok().filter(|_| !args.foreground);
+
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(filter)
+        .with(stderr_layer)
+        .with(journald_layer)
+        .init();
+
+    let _ = LOGGING.set(Logging {
+        dir,
+        file,
+        json: AtomicBool::new(false),
+        level,
+        fixed_level,
+        retention: Mutex::new((0, 0)),
+    });
+
+    thread::spawn(|| {
+        loop {
+            thread::sleep(PRUNE_INTERVAL);
+            prune_logs();
+        }
+    });
+}
+
+/// Applies the logging settings from the config, also when it's reloaded.
+pub fn apply(config: &Config) {
+    let Some(logging) = LOGGING.get() else {
+        return;
+    };
+
+    if !logging.fixed_level {
+        let level = config.debug();
+        match EnvFilter::try_new(&level) {
+            Ok(filter) => {
+                if let Err(e) = logging.level.reload(filter) {
+                    tracing::error!("Error changing log level: {e}");
+                }
+            }
+            Err(e) => tracing::error!("Invalid log level {level:?}: {e}"),
+        }
+    }
+
+    let json = config.log_json();
+    if logging.json.swap(json, Ordering::Relaxed) != json
+        && let Err(e) = logging.file.reload(file_layer(&logging.dir, json))
+    {
+        tracing::error!("Error changing log format: {e}");
+    }
+
+    *logging
+        .retention
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = (config.log_max_files(), config.log_max_age());
+    prune_logs();
+}
+
+fn file_layer(dir: &Path, json: bool) -> FileLayer {
+    let appender = tracing_appender::rolling::daily(dir, utils::APPNAME);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(appender)
+        .with_ansi(false);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+fn prune_logs() {
+    let Some(logging) = LOGGING.get() else {
+        return;
+    };
+    let (max_files, max_age) = *logging
+        .retention
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Err(e) = prune(&logging.dir, max_files, max_age) {
+        tracing::warn!("Error removing old log files: {e}");
+    }
+}
+
+// Keeps the newest `max_files` log files that are at most `max_age` days old. The newest one is
+// always kept, the appender is writing to it.
+fn prune(dir: &Path, max_files: usize, max_age: u64) -> io::Result<()> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(utils::APPNAME))
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect::<Vec<_>>();
+    files.sort_by_key(|&(modified, _)| Reverse(modified));
+
+    let now = SystemTime::now();
+    let max_age = DAY * u32::try_from(max_age).unwrap_or(u32::MAX);
+    for (i, (modified, path)) in files.into_iter().enumerate().skip(1) {
+        let too_many = max_files > 0 && i >= max_files;
+        let too_old =
+            !max_age.is_zero() && now.duration_since(modified).unwrap_or_default() > max_age;
+        if too_many || too_old {
+            tracing::debug!("Removing old log file {}", path.display());
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn test_prune() {
+        let dir = env::temp_dir().join(format!("walrus-logs-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let now = SystemTime::now();
+        for days in 0..5 {
+            let file = File::create(dir.join(format!("walrus.2024-01-0{}", 5 - days))).unwrap();
+            file.set_modified(now - DAY * days).unwrap();
+        }
+        File::create(dir.join("notes.txt")).unwrap();
+        let names = || {
+            let mut names = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        prune(&dir, 4, 0).unwrap();
+        assert_eq!(names().len(), 5);
+        assert!(!dir.join("walrus.2024-01-01").exists());
+
+        // Files from more than two days ago.
+        prune(&dir, 0, 2).unwrap();
+        assert_eq!(
+            names(),
+            ["notes.txt", "walrus.2024-01-04", "walrus.2024-01-05"]
+        );
+
+        // Even when it's old itself.
+        for (name, days) in [("walrus.2024-01-04", 8), ("walrus.2024-01-05", 7)] {
+            let file = File::options().append(true).open(dir.join(name)).unwrap();
+            file.set_modified(now - DAY * days).unwrap();
+        }
+        prune(&dir, 0, 2).unwrap();
+        assert_eq!(names(), ["notes.txt", "walrus.2024-01-05"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
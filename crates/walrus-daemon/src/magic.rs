@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use bincode::Decode;
+use bincode::Encode;
+
+// Enough for every signature below, including a few ftyp brands and leading whitespace in SVGs.
+const HEADER_LEN: usize = 512;
+const TGA_FOOTER: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+// How much of a PNG, WebP or AVIF file is read to tell whether it's animated.
+const ANIMATION_HEADER_LEN: u64 = 64 * 1024;
+
+/// Image formats recognised by their contents rather than their file extension.
+#[derive(Clone, Copy, Debug, Decode, Encode, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    WebP,
+    Gif,
+    Avif,
+    Bmp,
+    Tiff,
+    Pnm,
+    Tga,
+    Farbfeld,
+    Svg,
+}
+
+impl ImageFormat {
+    /// Identifies the format of the file at `path`, returning `None` if it isn't an image.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        file.by_ref()
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+
+        if let Some(format) = Self::from_header(&header, len) {
+            return Ok(Some(format));
+        }
+
+        // TGA has no magic number at the start. Newer files end in a footer, older ones can only
+        // be told apart by their extension and a plausible header.
+        let mut footer = [0u8; TGA_FOOTER.len()];
+        let has_footer = file.seek(SeekFrom::End(-18)).is_ok()
+            && file.read_exact(&mut footer).is_ok()
+            && &footer == TGA_FOOTER;
+        let has_extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("tga"));
+
+        if has_footer || (has_extension && is_tga_header(&header)) {
+            return Ok(Some(Self::Tga));
+        }
+        Ok(None)
+    }
+
+    // `len` is the length of the whole file.
+    fn from_header(header: &[u8], len: u64) -> Option<Self> {
+        match header {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some(Self::Png),
+            [0xFF, 0xD8, 0xFF, ..] => Some(Self::Jpeg),
+            [b'R', b'I', b'F', b'F', _, _, _, _, rest @ ..] if rest.starts_with(b"WEBP") => {
+                Some(Self::WebP)
+            }
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::Gif),
+            [b'B', b'M', ..] if is_bmp(header, len) => Some(Self::Bmp),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(Self::Tiff),
+            [b'P', b'1'..=b'7', b' ' | b'\t' | b'\r' | b'\n', ..] => Some(Self::Pnm),
+            [b'f', b'a', b'r', b'b', b'f', b'e', b'l', b'd', ..] => Some(Self::Farbfeld),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] if is_avif(header) => Some(Self::Avif),
+            _ if is_svg(header) => Some(Self::Svg),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the image has more than one frame. Animated images are shown as they are, decoding
+/// them to scale or slice them would only keep the first frame.
+pub fn is_animated(path: &Path) -> bool {
+    let Ok(Some(format)) = ImageFormat::detect(path) else {
+        return false;
+    };
+    let mut data = Vec::new();
+    let read = File::open(path).and_then(|file| match format {
+        // The second frame can be anywhere in the file.
+        ImageFormat::Gif => file.take(u64::MAX).read_to_end(&mut data),
+        // acTL comes before the image data, so it's near the start.
+        _ => file.take(ANIMATION_HEADER_LEN).read_to_end(&mut data),
+    });
+    read.is_ok() && animated(format, &data)
+}
+
+fn animated(format: ImageFormat, data: &[u8]) -> bool {
+    match format {
+        ImageFormat::Gif => gif_frames(data).is_some_and(|frames| frames > 1),
+        // The VP8X chunk's flags have a bit for animations.
+        ImageFormat::WebP => {
+            data.get(12..16) == Some(b"VP8X") && data.get(20).is_some_and(|f| f & 0x02 != 0)
+        }
+        ImageFormat::Png => is_apng(data),
+        ImageFormat::Avif => has_brand(data, *b"avis"),
+        _ => false,
+    }
+}
+
+// Counts the image descriptors, stopping at the second one.
+fn gif_frames(data: &[u8]) -> Option<usize> {
+    // Colour tables hold 2^(n + 1) RGB entries, n being the lowest three bits of the flags.
+    let table_len = |flags: u8| {
+        if flags & 0x80 == 0 {
+            0
+        } else {
+            3 << ((flags & 0x07) + 1)
+        }
+    };
+    let skip_sub_blocks = |mut pos: usize| loop {
+        let len = usize::from(*data.get(pos)?);
+        pos += 1 + len;
+        if len == 0 {
+            return Some(pos);
+        }
+    };
+
+    let mut pos = 13 + table_len(*data.get(10)?);
+    let mut frames = 0;
+    while frames < 2 {
+        match data.get(pos) {
+            // Extension: label, then sub-blocks.
+            Some(0x21) => pos = skip_sub_blocks(pos + 2)?,
+            // Image descriptor: position, size and flags, then the LZW code size and sub-blocks.
+            Some(0x2C) => {
+                frames += 1;
+                pos += 10 + table_len(*data.get(pos + 9)?);
+                pos = skip_sub_blocks(pos + 1)?;
+            }
+            // The trailer, or garbage.
+            _ => break,
+        }
+    }
+    Some(frames)
+}
+
+// An animated PNG has an acTL chunk before its first IDAT chunk.
+fn is_apng(data: &[u8]) -> bool {
+    let mut pos = 8;
+    while let Some(chunk) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        match &chunk[4..] {
+            b"acTL" => return true,
+            b"IDAT" => return false,
+            _ => pos += 12 + len,
+        }
+    }
+    false
+}
+
+/// Width and height read from the image's header, without decoding it.
+pub fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let size = imagesize::size(path).ok()?;
+    Some((
+        u32::try_from(size.width).ok()?,
+        u32::try_from(size.height).ok()?,
+    ))
+}
+
+fn is_avif(header: &[u8]) -> bool {
+    has_brand(header, *b"avif") || has_brand(header, *b"avis")
+}
+
+// The ftyp box lists a major brand followed by the minor version and compatible brands.
+fn has_brand(header: &[u8], brand: [u8; 4]) -> bool {
+    let Some(len) = header.get(..4) else {
+        return false;
+    };
+    let box_len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    let brands = header.get(8..box_len.min(header.len())).unwrap_or_default();
+
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        // Skip the minor version, it isn't a brand.
+        .filter(|(i, _)| *i != 1)
+        .any(|(_, b)| b == brand)
+}
+
+// "BM" alone is too likely to start a text file. The header after it holds the file's length, and
+// the DIB header that follows starts with its own length, which differs between versions.
+fn is_bmp(header: &[u8], len: u64) -> bool {
+    let Some(fields) = header.get(2..18) else {
+        return false;
+    };
+    let file_len = u32::from_le_bytes([fields[0], fields[1], fields[2], fields[3]]);
+    let dib_len = u32::from_le_bytes([fields[12], fields[13], fields[14], fields[15]]);
+    u64::from(file_len) == len && matches!(dib_len, 12 | 40 | 52 | 56 | 64 | 108 | 124)
+}
+
+fn is_svg(header: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(header);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    (text.starts_with("<?xml") || text.starts_with("<svg") || text.starts_with("<!--"))
+        && text.contains("<svg")
+}
+
+fn is_tga_header(header: &[u8]) -> bool {
+    header.len() >= 18
+        && matches!(header[1], 0 | 1)
+        && matches!(header[2], 1 | 2 | 3 | 9 | 10 | 11)
+        && matches!(header[16], 8 | 15 | 16 | 24 | 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magic_bytes() {
+        let cases: [(&[u8], Option<ImageFormat>); 15] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some(ImageFormat::Png)),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some(ImageFormat::Jpeg)),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some(ImageFormat::WebP)),
+            (b"GIF89a\x01\0\x01\0", Some(ImageFormat::Gif)),
+            (
+                b"\0\0\0\x1cftypmif1\0\0\0\0mif1avifmiaf",
+                Some(ImageFormat::Avif),
+            ),
+            (b"\0\0\0\x18ftypheic\0\0\0\0mif1heic", None),
+            (
+                b"BM\x12\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0",
+                Some(ImageFormat::Bmp),
+            ),
+            (b"BM\x12\0\0\0\0\0\0\0\x36\0\0\0\x29\0\0\0", None),
+            (b"BMW service history, 2019 to 2024\n", None),
+            (b"II*\0\x08\0\0\0", Some(ImageFormat::Tiff)),
+            (b"P6\n1920 1080\n255\n", Some(ImageFormat::Pnm)),
+            (b"farbfeld\0\0\0\x01", Some(ImageFormat::Farbfeld)),
+            (
+                b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\">",
+                Some(ImageFormat::Svg),
+            ),
+            (b"<?xml version=\"1.0\"?>\n<rss>", None),
+            (b"Photo by someone on Unsplash\n", None),
+        ];
+
+        for (header, expected) in cases {
+            assert_eq!(
+                ImageFormat::from_header(header, header.len() as u64),
+                expected,
+                "{}",
+                String::from_utf8_lossy(header)
+            );
+        }
+    }
+
+    #[test]
+    fn test_animated() {
+        // A 1x1 GIF with a two colour global table, an extension and one frame.
+        let mut gif = b"GIF89a\x01\0\x01\0\x80\0\0".to_vec();
+        gif.extend([0, 0, 0, 255, 255, 255]);
+        gif.extend(b"\x21\xf9\x04\0\0\0\0\0");
+        let frame = b"\x2c\0\0\0\0\x01\0\x01\0\0\x02\x02\x4c\x01\0";
+        gif.extend(frame);
+        assert!(!animated(
+            ImageFormat::Gif,
+            &[gif.as_slice(), b"\x3b"].concat()
+        ));
+        gif.extend(frame);
+        assert!(animated(
+            ImageFormat::Gif,
+            &[gif.as_slice(), b"\x3b"].concat()
+        ));
+
+        let webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0";
+        assert!(animated(
+            ImageFormat::WebP,
+            &[webp.as_slice(), b"\x02"].concat()
+        ));
+        assert!(!animated(
+            ImageFormat::WebP,
+            &[webp.as_slice(), b"\x10"].concat()
+        ));
+        assert!(!animated(ImageFormat::WebP, b"RIFF\0\0\0\0WEBPVP8 "));
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\0\0\0\0";
+        assert!(animated(
+            ImageFormat::Png,
+            &[png.as_slice(), b"\0\0\0\x08acTL"].concat()
+        ));
+        assert!(!animated(
+            ImageFormat::Png,
+            &[png.as_slice(), b"\0\0\0\0IDAT"].concat()
+        ));
+    }
+}
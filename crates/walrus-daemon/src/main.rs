@@ -1,27 +1,58 @@
 #![warn(clippy::pedantic)]
 
 use std::fs;
-use std::path::PathBuf;
 use std::process;
 use std::sync::mpsc;
+use std::thread;
 
+use clap::Parser;
+use cli::Args;
 use daemon::Daemon;
-use tracing::Subscriber;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::SIGINT;
+use signal_hook::consts::SIGTERM;
+use signal_hook::consts::SIGUSR1;
+use signal_hook::consts::SIGUSR2;
+use signal_hook::iterator::Signals;
 use walrus_core::commands::Commands;
 use walrus_core::config::Config;
 use walrus_core::ipc;
+use walrus_core::systemd;
 use walrus_core::utils;
 use walrus_core::utils::DirError;
 use walrus_core::utils::Dirs;
 
+mod backend;
+mod cache;
+mod category;
+mod change;
+mod cli;
 mod daemon;
+mod dedupe;
+mod hash;
+mod hooks;
+mod logging;
+mod magic;
+mod palette;
+mod power;
+mod prescale;
+mod scan;
+mod schedule;
+mod span;
+mod state;
+mod swww;
+mod tags;
+mod theme;
 mod transition;
 
 fn main() {
+    let args = Args::parse();
+    if let Some(socket) = args.socket.clone() {
+        ipc::set_socket_path(socket);
+    }
+
     // Start logging to file (and journald if it's available).
-    let log_dir = match utils::get_app_dir_with(Dirs::State, "logs") {
+    let log_dir = match utils::get_app_dir_with(Dirs::State, utils::LOG_DIR) {
         Ok(p) => p,
         Err(DirError::DoesNotExist(path)) => {
             fs::create_dir_all(&path).expect("Error creating directories for log file");
@@ -32,15 +63,30 @@ fn main() {
             process::exit(1);
         }
     };
-    daemon_logger(log_dir).init();
+    logging::init(log_dir, &args);
 
-    let config = Config::new().unwrap_or_else(|e| {
+    // Before loading anything, so another instance can't be writing the cache meanwhile.
+    let lock = if args.replace {
+        ipc::replace().unwrap_or_else(|e| {
+            tracing::error!("Error replacing the running instance: {e}");
+            process::exit(1);
+        })
+    } else {
+        ipc::lock_instance()
+    };
+
+    let config = match &args.config {
+        Some(path) => Config::from_file(path),
+        None => Config::new(),
+    };
+    let config = config.unwrap_or_else(|e| {
         tracing::error!("Error in config: {e}");
         tracing::warn!("Falling back to default config...");
         Config::default()
     });
+    logging::apply(&config);
 
-    let mut daemon = Daemon::new(config);
+    let mut daemon = Daemon::new(config, args.config.clone());
     if daemon.queue.is_empty() {
         tracing::info!("Queue is empty, exiting...");
         return;
@@ -50,7 +96,12 @@ fn main() {
 
     // Start watching the Config.toml for changes. This spawns a detached thread.
     // NOTE: In the future I might want to return a join handle here to clean up and retry on fail.
-    match utils::get_config_file("config.toml") {
+    let config_path = match &args.config {
+        Some(path) => Ok(path.clone()),
+        None => utils::get_config_file("config.toml"),
+    };
+    match config_path {
+        _ if args.no_watch => tracing::debug!("Not watching the config for changes"),
         Ok(path) => match Config::watch(path, tx.clone()) {
             Ok(()) => tracing::debug!("Starting inotify service"),
             Err(e) => {
@@ -66,28 +117,28 @@ fn main() {
         }
     }
 
-    let ctrlc_tx = tx.clone();
-    ctrlc::set_handler(move || {
-        let _ = ctrlc_tx.send(Commands::Shutdown);
-    })
-    .expect("Error setting Ctrl-C handler");
-
-    let _ipc = ipc::start_server(tx.clone());
-
-    daemon.run(&rx);
-}
+    // Signals are forwarded as commands, for keybinding daemons and `pkill -USR1 walrus-daemon`.
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2])
+        .expect("Error setting signal handlers");
+    let signal_tx = tx.clone();
+    thread::spawn(move || {
+        for signal in &mut signals {
+            let command = match signal {
+                SIGHUP => Commands::Reload,
+                SIGUSR1 => Commands::Next,
+                SIGUSR2 => Commands::Toggle,
+                // SIGINT and SIGTERM
+                _ => Commands::Shutdown,
+            };
+            tracing::debug!("Received signal {signal}, sending {command:?} command");
+            if signal_tx.send(command.into()).is_err() {
+                break;
+            }
+        }
+    });
 
-#[must_use]
-pub fn daemon_logger(log_dir: PathBuf) -> impl Subscriber {
-    let file_appender = tracing_appender::rolling::daily(log_dir, "walrus");
-    let file_layer = tracing_subscriber::fmt::layer()
-        .with_writer(file_appender)
-        .with_ansi(false);
-    let subscriber = tracing_subscriber::registry().with(file_layer);
+    let _ipc = ipc::start_server(tx.clone(), lock);
+    systemd::notify("READY=1");
 
-    // Some systems might not have journald or connecting to the socket could fail for whatever
-    // reason. If it does fail this will return None and the layer will just do nothing.
-    // https://docs.rs/tracing-subscriber/0.3.20/tracing_subscriber/layer/index.html#runtime-configuration-with-layers
-    let journald_layer = tracing_journald::layer().ok();
-    subscriber.with(journald_layer)
+    daemon.run(rx);
 }
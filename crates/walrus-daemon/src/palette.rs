@@ -0,0 +1,290 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bincode::Decode;
+use bincode::Encode;
+use image::ImageReader;
+use image::ImageResult;
+
+/// Number of colours extracted from every wallpaper, the same as a terminal colour scheme.
+pub const PALETTE_SIZE: usize = 16;
+// Images are shrunk to fit this before sampling. Plenty for finding dominant colours.
+const SAMPLE_SIZE: u32 = 128;
+// Even, so the lightness sweeps line up when the order wraps around, see `colour_order`.
+const HUE_BUCKETS: usize = 12;
+// Below this chroma, an image's hue is mostly noise. Such images get a bucket of their own.
+const NEUTRAL_CHROMA: f64 = 8.0;
+
+#[derive(Clone, Copy, Debug, Decode, Encode, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// `#rrggbb`
+    pub fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Relative luminance, from 0 for black to 1 for white.
+    pub fn luminance(self) -> f64 {
+        let [r, g, b] = self.linear();
+        0.0722f64.mul_add(b, 0.2126f64.mul_add(r, 0.7152 * g))
+    }
+
+    pub fn to_lab(self) -> Lab {
+        let [red, green, blue] = self.linear();
+        // Relative to the D65 white point.
+        let x = 0.1805f64.mul_add(blue, 0.4124f64.mul_add(red, 0.3576 * green)) / 0.950_47;
+        let y = self.luminance();
+        let z = 0.9505f64.mul_add(blue, 0.0193f64.mul_add(red, 0.1192 * green)) / 1.088_83;
+
+        let f = |t: f64| {
+            if t > 0.008_856 {
+                t.cbrt()
+            } else {
+                7.787f64.mul_add(t, 16.0 / 116.0)
+            }
+        };
+        Lab {
+            l: 116f64.mul_add(f(y), -16.0),
+            a: 500.0 * (f(x) - f(y)),
+            b: 200.0 * (f(y) - f(z)),
+        }
+    }
+
+    fn linear(self) -> [f64; 3] {
+        [self.r, self.g, self.b].map(|c| {
+            let c = f64::from(c) / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    }
+}
+
+/// CIELAB colour, where distances roughly match how different colours look.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Lab {
+    /// Mean of the colours, `None` if there are none.
+    pub fn average(colors: &[Color]) -> Option<Self> {
+        #[allow(clippy::cast_precision_loss)]
+        let len = colors.len() as f64;
+        let sum = colors.iter().map(|c| c.to_lab()).reduce(|sum, lab| Lab {
+            l: sum.l + lab.l,
+            a: sum.a + lab.a,
+            b: sum.b + lab.b,
+        })?;
+        Some(Lab {
+            l: sum.l / len,
+            a: sum.a / len,
+            b: sum.b / len,
+        })
+    }
+
+    /// Distance from grey, 0 for black, white and any grey in between.
+    pub fn chroma(self) -> f64 {
+        self.a.hypot(self.b)
+    }
+
+    /// Hue angle in degrees, from 0 up to 360.
+    pub fn hue(self) -> f64 {
+        self.b.atan2(self.a).to_degrees().rem_euclid(360.0)
+    }
+}
+
+/// Dominant colours of the image, darkest first. Images with few colours can have fewer than
+/// `PALETTE_SIZE`, but never none.
+pub fn extract(path: &Path) -> ImageResult<Vec<Color>> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    let pixels = image
+        .thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+        .to_rgb8()
+        .pixels()
+        .map(|pixel| pixel.0)
+        .collect::<Vec<_>>();
+
+    let mut palette = median_cut(pixels, PALETTE_SIZE);
+    palette.sort_by(|a, b| a.luminance().total_cmp(&b.luminance()));
+    Ok(palette)
+}
+
+/// Orders wallpapers by their average colour, so each one looks like the one before it and a full
+/// cycle sweeps around the hue wheel. Wallpapers are split into slices of the hue wheel, and every
+/// slice runs from dark to light or the other way around, depending on where the last one ended.
+/// Near-neutral wallpapers, which have no hue to speak of, come last from dark to light.
+pub fn colour_order(mut wallpapers: Vec<(PathBuf, Lab)>) -> Vec<PathBuf> {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let bucket = |lab: Lab| {
+        if lab.chroma() < NEUTRAL_CHROMA {
+            // Even, so it runs from dark to light.
+            HUE_BUCKETS
+        } else {
+            (lab.hue() / 360.0 * HUE_BUCKETS as f64) as usize % HUE_BUCKETS
+        }
+    };
+
+    wallpapers.sort_by(|(_, a), (_, b)| {
+        let (a_bucket, b_bucket) = (bucket(*a), bucket(*b));
+        a_bucket.cmp(&b_bucket).then_with(|| {
+            if a_bucket % 2 == 0 {
+                a.l.total_cmp(&b.l)
+            } else {
+                b.l.total_cmp(&a.l)
+            }
+        })
+    });
+    wallpapers.into_iter().map(|(path, _)| path).collect()
+}
+
+// Repeatedly halves the box of pixels with the widest range in any channel along that channel, then
+// averages every box.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<Color> {
+    if pixels.is_empty() {
+        return vec![Color { r: 0, g: 0, b: 0 }];
+    }
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| {
+                let (channel, range) = widest_channel(pixels);
+                (i, channel, range)
+            })
+            .filter(|&(_, _, range)| range > 0)
+            .max_by_key(|&(_, _, range)| range);
+        // Every box is a single colour, there is nothing left to split.
+        let Some((i, channel, _)) = widest else {
+            break;
+        };
+
+        let mut pixels = boxes.swap_remove(i);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|pixels| average(pixels)).collect()
+}
+
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), pixel| {
+                (min.min(pixel[channel]), max.max(pixel[channel]))
+            });
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or_default()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn average(pixels: &[[u8; 3]]) -> Color {
+    let mut sums = [0u64; 3];
+    for pixel in pixels {
+        for (sum, &c) in sums.iter_mut().zip(pixel) {
+            *sum += u64::from(c);
+        }
+    }
+    // Every box holds at least one pixel, and the average of u8s fits in a u8.
+    let len = pixels.len() as u64;
+    let [r, g, b] = sums.map(|sum| (sum / len) as u8);
+    Color { r, g, b }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_cut() {
+        let colors = [[0, 0, 0], [0, 0, 200], [200, 0, 0], [200, 0, 200]];
+        let pixels = colors.iter().flat_map(|&c| [c; 25]).collect();
+
+        // Splitting stops once every box holds a single colour.
+        let mut palette = median_cut(pixels, PALETTE_SIZE);
+        palette.sort_by(|a, b| a.luminance().total_cmp(&b.luminance()));
+
+        assert_eq!(palette, colors.map(|[r, g, b]| Color { r, g, b }).to_vec());
+        assert_eq!(palette[2].hex(), "#c80000");
+    }
+
+    #[test]
+    fn test_colour_order() {
+        let lab = |r, g, b| Color { r, g, b }.to_lab();
+        let white = lab(255, 255, 255);
+        assert!((white.l - 100.0).abs() < 0.1 && white.a.abs() < 0.1 && white.b.abs() < 0.1);
+
+        let wallpapers = vec![
+            (PathBuf::from("pink"), lab(255, 150, 150)),
+            (PathBuf::from("dark-red"), lab(90, 0, 0)),
+            (PathBuf::from("navy"), lab(20, 20, 120)),
+            (PathBuf::from("light-red"), lab(255, 120, 120)),
+            (PathBuf::from("red"), lab(200, 30, 30)),
+            (PathBuf::from("dark-blue"), lab(0, 0, 90)),
+            (PathBuf::from("green"), lab(0, 160, 0)),
+        ];
+
+        // Pinks get lighter, reds then get darker again, and blues lighter.
+        assert_eq!(
+            colour_order(wallpapers),
+            [
+                "light-red",
+                "pink",
+                "red",
+                "dark-red",
+                "green",
+                "dark-blue",
+                "navy"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_colour_order_greyscale() {
+        let lab = |r, g, b| Color { r, g, b }.to_lab();
+        // Greys that are slightly off in different directions, which would scatter their hues.
+        let wallpapers = vec![
+            (PathBuf::from("light-grey"), lab(200, 201, 200)),
+            (PathBuf::from("red"), lab(200, 30, 30)),
+            (PathBuf::from("black"), lab(0, 0, 0)),
+            (PathBuf::from("grey"), lab(100, 100, 102)),
+            (PathBuf::from("white"), lab(255, 255, 255)),
+            (PathBuf::from("dark-grey"), lab(41, 40, 40)),
+            (PathBuf::from("blue"), lab(20, 20, 120)),
+        ];
+
+        assert_eq!(
+            colour_order(wallpapers),
+            [
+                "red",
+                "blue",
+                "black",
+                "dark-grey",
+                "grey",
+                "light-grey",
+                "white"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+}
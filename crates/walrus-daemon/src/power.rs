@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+
+// Under the sysfs root, see the kernel's sysfs-class-power documentation.
+const POWER_SUPPLY_DIR: &str = "class/power_supply";
+
+/// Whether the system runs on battery, and how full its batteries are.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Power {
+    pub on_battery: bool,
+    // Average charge of the batteries in percent.
+    pub capacity: Option<u8>,
+}
+
+/// Reads the power supplies under `sysfs`, normally /sys. Systems without batteries, or where
+/// they can't be read, are never on battery.
+pub fn read(sysfs: &Path) -> Power {
+    let Ok(entries) = fs::read_dir(sysfs.join(POWER_SUPPLY_DIR)) else {
+        return Power::default();
+    };
+
+    let mut capacities = Vec::new();
+    let mut discharging = false;
+    let mut external = false;
+    for entry in entries.filter_map(Result::ok) {
+        let dir = entry.path();
+        let read = |name: &str| {
+            fs::read_to_string(dir.join(name))
+                .map(|value| value.trim().to_owned())
+                .unwrap_or_default()
+        };
+        match read("type").as_str() {
+            // Mice and headsets report their batteries too.
+            "Battery" if read("scope") != "Device" => {
+                if let Ok(capacity) = read("capacity").parse::<u8>() {
+                    capacities.push(u32::from(capacity));
+                }
+                discharging |= read("status") == "Discharging";
+            }
+            "Mains" | "USB" => external |= read("online") == "1",
+            _ => {}
+        }
+    }
+
+    let capacity = u32::try_from(capacities.len())
+        .ok()
+        .filter(|&count| count > 0)
+        .map(|count| capacities.iter().sum::<u32>() / count)
+        .and_then(|capacity| u8::try_from(capacity).ok());
+    Power {
+        on_battery: discharging && !external,
+        capacity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let sysfs = env::temp_dir().join(format!("walrus-sysfs-{}", process::id()));
+        let _ = fs::remove_dir_all(&sysfs);
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            let dir = sysfs.join(POWER_SUPPLY_DIR).join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for (file, value) in files {
+                fs::write(dir.join(file), format!("{value}\n")).unwrap();
+            }
+        };
+
+        assert_eq!(read(&sysfs), Power::default());
+
+        supply(
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "40"),
+            ],
+        );
+        supply(
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "60"),
+            ],
+        );
+        supply(
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(
+            read(&sysfs),
+            Power {
+                on_battery: true,
+                capacity: Some(50),
+            }
+        );
+
+        supply("AC", &[("online", "1")]);
+        assert!(!read(&sysfs).on_battery);
+
+        fs::remove_dir_all(&sysfs).unwrap();
+    }
+}
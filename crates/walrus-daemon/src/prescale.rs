@@ -0,0 +1,236 @@
+use std::cmp::Reverse;
+use std::fmt::Write;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use image::DynamicImage;
+use image::ImageReader;
+use image::ImageResult;
+use image::codecs::png::CompressionType;
+use image::codecs::png::FilterType as PngFilter;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use sha2::Digest;
+use sha2::Sha256;
+use walrus_core::config::FilterMethod;
+use walrus_core::config::ResizeMethod;
+
+use crate::magic;
+
+static RENDERING: Mutex<()> = Mutex::new(());
+
+/// Renders `source` for an output of `size` the way swww would with `filter` and `resize`, and
+/// stores the result in `dir`. Rendered images are reused until the source changes, and the file
+/// is touched every time so `evict` can tell which ones were used last.
+///
+/// Images that aren't bigger than the output are returned as they are, there is nothing to gain
+/// from scaling those up front. So are animated images, which would lose every frame but the
+/// first.
+pub fn prescale(
+    source: &Path,
+    dimensions: (u32, u32),
+    size: (u32, u32),
+    filter: &FilterMethod,
+    resize: &ResizeMethod,
+    dir: &Path,
+) -> ImageResult<PathBuf> {
+    if (dimensions.0 <= size.0 && dimensions.1 <= size.1) || magic::is_animated(source) {
+        return Ok(source.to_owned());
+    }
+
+    let params = format!("{}x{} {filter} {resize}", size.0, size.1);
+    let path = dir.join(format!("{}.png", key(source, &params)?));
+    let _rendering = rendering();
+    if path.is_file() {
+        touch(&path)?;
+        return Ok(path);
+    }
+
+    let image = ImageReader::open(source)?.with_guessed_format()?.decode()?;
+    save(&scale(&image, size, filter, resize), &path)?;
+    Ok(path)
+}
+
+/// Held while rendering, so an image that is being rendered in the background isn't rendered a
+/// second time when it gets set meanwhile.
+pub fn rendering() -> MutexGuard<'static, ()> {
+    RENDERING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Marks a cached image as used, see `evict`.
+pub fn touch(path: &Path) -> io::Result<()> {
+    File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Stores a rendered image as PNG, creating its directory if needed.
+pub fn save(image: &DynamicImage, path: &Path) -> ImageResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Write to a temporary file first so a crash can't leave a truncated image behind.
+    let tmp = path.with_extension("tmp");
+    let writer = BufWriter::new(File::create(&tmp)?);
+    // Speed matters more than size here, the cache is bounded anyway.
+    image.write_with_encoder(PngEncoder::new_with_quality(
+        writer,
+        CompressionType::Fast,
+        PngFilter::Adaptive,
+    ))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Deletes the least recently used images in `dir` until it takes up at most `max_size` bytes.
+pub fn evict(dir: &Path, max_size: u64) -> io::Result<()> {
+    // Not while an image is being written.
+    let _rendering = rendering();
+    let mut files = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|&(modified, _, _)| Reverse(modified));
+
+    let mut total = 0;
+    for (_, len, path) in files {
+        total += len;
+        if total > max_size {
+            tracing::debug!("Evicting {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn scale(
+    image: &DynamicImage,
+    (width, height): (u32, u32),
+    filter: &FilterMethod,
+    resize: &ResizeMethod,
+) -> DynamicImage {
+    let filter = filter_type(filter);
+    match resize {
+        ResizeMethod::Crop => image.resize_to_fill(width, height, filter),
+        ResizeMethod::Fit => image.resize(width, height, filter),
+        // Without resizing swww shows the middle of the image, so only that part is kept.
+        ResizeMethod::No => {
+            let (w, h) = (width.min(image.width()), height.min(image.height()));
+            image.crop_imm((image.width() - w) / 2, (image.height() - h) / 2, w, h)
+        }
+    }
+}
+
+pub fn filter_type(filter: &FilterMethod) -> FilterType {
+    match filter {
+        FilterMethod::Nearest => FilterType::Nearest,
+        FilterMethod::Bilinear => FilterType::Triangle,
+        // The image crate has no Mitchell filter, Catmull-Rom is the closest cubic one.
+        FilterMethod::CatmullRom | FilterMethod::Mitchell => FilterType::CatmullRom,
+        FilterMethod::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+/// Name for an image rendered from `source`, which changes whenever the source file or `params`
+/// do. `params` should describe everything else that affects the result.
+pub fn key(source: &Path, params: &str) -> io::Result<String> {
+    let metadata = fs::metadata(source)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_os_str().as_encoded_bytes());
+    hasher.update(format!("\0{modified}\0{}\0{params}", metadata.len()));
+    // Half of the hash is plenty to tell cached images apart.
+    Ok(hasher.finalize()[..16]
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use super::*;
+    use crate::magic;
+
+    #[test]
+    fn test_prescale() {
+        let dir = env::temp_dir().join(format!("walrus-prescale-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("wide.png");
+        RgbImage::new(40, 20).save(&source).unwrap();
+        let scaled = dir.join("scaled");
+
+        let sizes = [
+            (ResizeMethod::Crop, (10, 10)),
+            (ResizeMethod::Fit, (10, 5)),
+            (ResizeMethod::No, (10, 10)),
+        ];
+        for (resize, expected) in sizes {
+            let path = prescale(
+                &source,
+                (40, 20),
+                (10, 10),
+                &FilterMethod::Nearest,
+                &resize,
+                &scaled,
+            )
+            .unwrap();
+            assert_eq!(magic::dimensions(&path), Some(expected), "{resize}");
+        }
+        // Images no bigger than the output are used as they are.
+        let path = prescale(
+            &source,
+            (40, 20),
+            (40, 40),
+            &FilterMethod::Nearest,
+            &ResizeMethod::Crop,
+            &scaled,
+        )
+        .unwrap();
+        assert_eq!(path, source);
+
+        // Only the most recently used image fits.
+        thread::sleep(Duration::from_millis(10));
+        let last = prescale(
+            &source,
+            (40, 20),
+            (10, 10),
+            &FilterMethod::Nearest,
+            &ResizeMethod::Crop,
+            &scaled,
+        )
+        .unwrap();
+        evict(&scaled, fs::metadata(&last).unwrap().len()).unwrap();
+        assert_eq!(fs::read_dir(&scaled).unwrap().count(), 1);
+        assert!(last.is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use globset::Glob;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use ignore::Match;
+use ignore::gitignore::Gitignore;
+use walkdir::DirEntry;
+use walkdir::WalkDir;
+use walrus_core::config::Config;
+use walrus_core::config::MonitorInfo;
+
+use crate::cache::Cache;
+use crate::category;
+
+pub const IGNORE_FILE: &str = ".walrusignore";
+
+/// Which parts of the wallpaper directory get scanned.
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    /// Directories to skip, relative to the scanned directory unless absolute.
+    pub ignored_dirs: Vec<PathBuf>,
+    /// Globs of files to include, relative to the scanned directory. Empty includes everything.
+    pub include: Vec<String>,
+    /// Globs of files and directories to skip, relative to the scanned directory.
+    pub exclude: Vec<String>,
+    pub max_depth: Option<usize>,
+    /// Minimum width and height per output, as seen on screen. Images smaller than any output
+    /// they could be shown on are skipped, comparing long and short sides regardless of
+    /// orientation.
+    pub min_resolutions: Vec<(u32, u32)>,
+    /// Only outputs matching an image's orientation count towards `min_resolutions`.
+    pub match_aspect: bool,
+    /// Only include portrait (or only landscape) images.
+    pub portrait: Option<bool>,
+}
+
+impl ScanOptions {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn from_config(config: &Config) -> Self {
+        let outputs = config.outputs();
+        let ratio = config.min_resolution_ratio();
+        let scale = |size: u32| (f64::from(size) * ratio) as u32;
+        let min_resolutions = if ratio <= 0.0 {
+            Vec::new()
+        } else if outputs.is_empty() {
+            let resolution = config.resolution();
+            vec![(
+                scale(resolution.width.unsigned_abs()),
+                scale(resolution.height.unsigned_abs()),
+            )]
+        } else {
+            outputs
+                .iter()
+                .map(MonitorInfo::screen_size)
+                .map(|(width, height)| (scale(width), scale(height)))
+                .collect()
+        };
+
+        // With outputs of mixed orientations every image has somewhere to go, see
+        // Daemon::set_wallpaper.
+        let portrait = outputs
+            .first()
+            .map(MonitorInfo::is_portrait)
+            .filter(|&portrait| {
+                config.match_aspect() && outputs.iter().all(|o| o.is_portrait() == portrait)
+            });
+
+        Self {
+            ignored_dirs: config.ignored_dirs(),
+            include: config.include(),
+            exclude: config.exclude(),
+            max_depth: config.max_depth(),
+            min_resolutions,
+            match_aspect: config.match_aspect(),
+            portrait,
+        }
+    }
+}
+
+/// Collects every wallpaper under `dir`.
+///
+/// Top-level hidden directories are categories managed by walrus and only contain symlinks to
+/// wallpapers, so they are never descended into. Neither are the directories of version control and
+/// sync tools (e.g. `.git`), wherever they are. Neither are ignored
+/// directories, excluded paths or anything matched by a `.walrusignore` file. Wallpapers reachable
+/// through more than one path are only included once.
+///
+/// Formats and sizes come from the cache, so only new or changed files have to be read. Files that
+/// have disappeared since the last scan are dropped from it.
+pub fn scan(dir: &Path, options: &ScanOptions, cache: &mut Cache) -> Vec<PathBuf> {
+    let ignored_dirs = options
+        .ignored_dirs
+        .iter()
+        .map(|ignored| dir.join(ignored))
+        .collect::<Vec<_>>();
+    let include = build_globs(&options.include);
+    let exclude = build_globs(&options.exclude);
+    let mut ignore_files = IgnoreStack::default();
+
+    // Disliked wallpapers stay where they are, the dislike category only links to them.
+    let disliked = category::wallpapers(dir, category::DISLIKE)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect::<HashSet<_>>();
+
+    // Duplicates keep the path that doesn't go through a symlink, if there is one.
+    let canonical_dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_owned());
+    let mut wallpapers = Vec::new();
+    let mut seen = HashMap::new();
+    let mut files = HashSet::new();
+
+    let mut walker = WalkDir::new(dir).follow_links(true);
+    if let Some(depth) = options.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    let entries = walker
+        .into_iter()
+        .filter_entry(|entry| {
+            let Ok(rel) = entry.path().strip_prefix(dir) else {
+                return false;
+            };
+            let keep = !is_skipped_dir(entry)
+                && !ignored_dirs.iter().any(|i| i == entry.path())
+                && !exclude.is_match(rel)
+                && !ignore_files.is_ignored(entry);
+
+            if keep && entry.file_type().is_dir() {
+                ignore_files.enter(entry.path());
+            }
+            keep
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter(|entry| entry.file_name() != IGNORE_FILE)
+        .filter(|entry| {
+            options.include.is_empty()
+                || entry
+                    .path()
+                    .strip_prefix(dir)
+                    .is_ok_and(|rel| include.is_match(rel))
+        });
+
+    for entry in entries {
+        files.insert(entry.path().to_owned());
+        let Ok(canonical) = fs::canonicalize(entry.path()) else {
+            continue;
+        };
+        if disliked.contains(&canonical) {
+            continue;
+        }
+
+        if let Some(&i) = seen.get(&canonical) {
+            if canonical.strip_prefix(&canonical_dir).ok() == entry.path().strip_prefix(dir).ok() {
+                wallpapers[i] = entry.into_path();
+            }
+        } else if is_image(entry.path(), cache) && fits(entry.path(), options, cache) {
+            seen.insert(canonical, wallpapers.len());
+            wallpapers.push(entry.into_path());
+        }
+    }
+
+    cache.prune(dir, &files);
+    wallpapers
+}
+
+fn is_image(path: &Path, cache: &mut Cache) -> bool {
+    match cache.format(path) {
+        Ok(Some(_)) => true,
+        Ok(None) => {
+            tracing::debug!("Skipping {}: not an image", path.display());
+            false
+        }
+        Err(e) => {
+            tracing::debug!("Skipping {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+// Images whose size can't be read from their header (e.g. SVGs) always fit.
+fn fits(path: &Path, options: &ScanOptions, cache: &mut Cache) -> bool {
+    let Some((width, height)) = cache.dimensions(path) else {
+        return true;
+    };
+
+    // Like Daemon::set_wallpaper, falls back to every output if none match the orientation.
+    let portrait = height > width;
+    let matching = options
+        .min_resolutions
+        .iter()
+        .filter(|&&(min_width, min_height)| {
+            !options.match_aspect || (min_height > min_width) == portrait
+        })
+        .collect::<Vec<_>>();
+    let outputs = if matching.is_empty() {
+        options.min_resolutions.iter().collect()
+    } else {
+        matching
+    };
+    let (long, short) = (width.max(height), width.min(height));
+    if outputs.iter().any(|&&(min_width, min_height)| {
+        long < min_width.max(min_height) || short < min_width.min(min_height)
+    }) {
+        tracing::debug!("Skipping {}: too small ({width}x{height})", path.display());
+        return false;
+    }
+
+    if let Some(portrait) = options.portrait
+        && (height > width) != portrait
+    {
+        tracing::debug!("Skipping {}: wrong orientation", path.display());
+        return false;
+    }
+
+    true
+}
+
+// Categories and the directories of other tools.
+fn is_skipped_dir(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir()
+        && entry.file_name().to_str().is_some_and(|name| {
+            category::is_tool_dir(name) || (entry.depth() == 1 && category::is_category(name))
+        })
+}
+
+// Invalid globs are logged and skipped so one typo doesn't stop every other glob from working.
+fn build_globs(globs: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        match Glob::new(glob) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => tracing::error!("Invalid glob {glob}: {e}"),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        tracing::error!("Error building globs: {e}");
+        GlobSet::empty()
+    })
+}
+
+// The .walrusignore files of the directory currently being walked and its ancestors. This relies on
+// the walk being depth first, so every directory is entered before its contents.
+#[derive(Default)]
+struct IgnoreStack {
+    ignores: Vec<(PathBuf, Gitignore)>,
+}
+
+impl IgnoreStack {
+    fn enter(&mut self, dir: &Path) {
+        let path = dir.join(IGNORE_FILE);
+        if !path.is_file() {
+            return;
+        }
+
+        let (gitignore, e) = Gitignore::new(&path);
+        if let Some(e) = e {
+            tracing::warn!("Error in {}: {e}", path.display());
+        }
+        self.ignores.push((dir.to_owned(), gitignore));
+    }
+
+    fn is_ignored(&mut self, entry: &DirEntry) -> bool {
+        // Leaving a directory drops its ignore file.
+        while let Some((dir, _)) = self.ignores.last()
+            && !entry.path().starts_with(dir)
+        {
+            self.ignores.pop();
+        }
+
+        // The deepest ignore file with an opinion on the path wins, like with .gitignore.
+        for (_, gitignore) in self.ignores.iter().rev() {
+            match gitignore.matched(entry.path(), entry.file_type().is_dir()) {
+                Match::None => {}
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix;
+    use std::process;
+
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn test_scan_exclusions() {
+        let dir = env::temp_dir().join(format!("walrus-scan-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nature")).unwrap();
+        fs::create_dir_all(dir.join("archive")).unwrap();
+        fs::create_dir_all(dir.join(".favourites/nature")).unwrap();
+        fs::create_dir_all(dir.join("nature/.git")).unwrap();
+
+        fs::write(dir.join("nature/lake.png"), PNG).unwrap();
+        fs::write(dir.join("city.png"), PNG).unwrap();
+        fs::write(dir.join("archive/old.png"), PNG).unwrap();
+        fs::write(dir.join("nature/.git/lake.png"), PNG).unwrap();
+        fs::write(dir.join("nature/credits.txt"), "Photo by someone").unwrap();
+        // Category symlink and a second path to the same file through a directory symlink.
+        unix::fs::symlink(
+            dir.join("nature/lake.png"),
+            dir.join(".favourites/nature/lake.png"),
+        )
+        .unwrap();
+        unix::fs::symlink(dir.join("nature"), dir.join("linked")).unwrap();
+
+        let options = ScanOptions {
+            ignored_dirs: vec![PathBuf::from("archive")],
+            ..ScanOptions::default()
+        };
+        let mut wallpapers = scan(&dir, &options, &mut Cache::default());
+        wallpapers.sort();
+
+        assert_eq!(
+            wallpapers,
+            vec![dir.join("city.png"), dir.join("nature/lake.png")]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scan_filters() {
+        let dir = env::temp_dir().join(format!("walrus-scan-filters-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested/deeper")).unwrap();
+
+        fs::write(dir.join("city.png"), PNG).unwrap();
+        fs::write(dir.join("credits.txt"), PNG).unwrap();
+        fs::write(dir.join("nested/lake.jpg"), PNG).unwrap();
+        fs::write(dir.join("nested/skip.jpg"), PNG).unwrap();
+        fs::write(dir.join("nested/keep.part"), PNG).unwrap();
+        fs::write(dir.join("nested/deeper/forest.png"), PNG).unwrap();
+        fs::write(dir.join(IGNORE_FILE), "*.part\n").unwrap();
+        fs::write(
+            dir.join("nested").join(IGNORE_FILE),
+            "skip.jpg\n!keep.part\n",
+        )
+        .unwrap();
+
+        let options = ScanOptions {
+            exclude: vec!["*.txt".into()],
+            max_depth: Some(2),
+            ..ScanOptions::default()
+        };
+        let mut wallpapers = scan(&dir, &options, &mut Cache::default());
+        wallpapers.sort();
+
+        assert_eq!(
+            wallpapers,
+            vec![
+                dir.join("city.png"),
+                dir.join("nested/keep.part"),
+                dir.join("nested/lake.jpg"),
+            ]
+        );
+
+        let options = ScanOptions {
+            include: vec!["**/*.png".into()],
+            ..ScanOptions::default()
+        };
+        let mut wallpapers = scan(&dir, &options, &mut Cache::default());
+        wallpapers.sort();
+
+        assert_eq!(
+            wallpapers,
+            vec![dir.join("city.png"), dir.join("nested/deeper/forest.png")]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits() {
+        let dir = env::temp_dir().join(format!("walrus-scan-fits-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Just enough of a PNG for its size to be read from the IHDR chunk.
+        let png = |width: u32, height: u32| {
+            let mut bytes = PNG.to_vec();
+            bytes.extend(b"\0\0\0\rIHDR");
+            bytes.extend(width.to_be_bytes());
+            bytes.extend(height.to_be_bytes());
+            bytes.extend([8, 6, 0, 0, 0]);
+            bytes
+        };
+        fs::write(dir.join("thumbnail.png"), png(640, 480)).unwrap();
+        fs::write(dir.join("landscape.png"), png(3840, 2160)).unwrap();
+        fs::write(dir.join("portrait.png"), png(2160, 3840)).unwrap();
+
+        let mut cache = Cache::default();
+        let options = ScanOptions {
+            min_resolutions: vec![(1920, 1080)],
+            ..ScanOptions::default()
+        };
+        assert!(!fits(&dir.join("thumbnail.png"), &options, &mut cache));
+        assert!(fits(&dir.join("landscape.png"), &options, &mut cache));
+        assert!(fits(&dir.join("portrait.png"), &options, &mut cache));
+
+        // A 4K image is too small for a 5K output, unless it only goes to the portrait one.
+        fs::write(dir.join("small-portrait.png"), png(1440, 2560)).unwrap();
+        let options = ScanOptions {
+            min_resolutions: vec![(5120, 2880), (1440, 2560)],
+            ..ScanOptions::default()
+        };
+        assert!(!fits(&dir.join("landscape.png"), &options, &mut cache));
+        assert!(!fits(&dir.join("small-portrait.png"), &options, &mut cache));
+        let options = ScanOptions {
+            match_aspect: true,
+            ..options
+        };
+        assert!(!fits(&dir.join("landscape.png"), &options, &mut cache));
+        assert!(fits(&dir.join("small-portrait.png"), &options, &mut cache));
+
+        let options = ScanOptions {
+            portrait: Some(true),
+            ..ScanOptions::default()
+        };
+        assert!(!fits(&dir.join("landscape.png"), &options, &mut cache));
+        assert!(fits(&dir.join("portrait.png"), &options, &mut cache));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
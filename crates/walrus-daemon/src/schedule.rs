@@ -0,0 +1,108 @@
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use walrus_core::utils;
+
+/// Longest the daemon waits without checking for a suspend. `Instant`s stand still while the
+/// system is suspended, so a wait for the next change would otherwise go on for as long as the
+/// system slept.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// How far the wall clock may get ahead of the monotonic clock between two checks without it
+// counting as a suspend, NTP adjusts the wall clock as well.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Notices the system having been suspended by comparing the wall clock with the monotonic clock.
+#[derive(Debug)]
+pub struct SuspendWatch {
+    wall: SystemTime,
+    monotonic: Instant,
+}
+
+impl SuspendWatch {
+    pub fn new() -> Self {
+        Self {
+            wall: SystemTime::now(),
+            monotonic: Instant::now(),
+        }
+    }
+
+    /// How long the system was suspended since the last check, if it was.
+    pub fn check(&mut self) -> Option<Duration> {
+        let (wall, monotonic) = (SystemTime::now(), Instant::now());
+        let suspended = suspended(
+            wall.duration_since(self.wall).unwrap_or_default(),
+            monotonic - self.monotonic,
+        );
+        self.wall = wall;
+        self.monotonic = monotonic;
+        suspended
+    }
+}
+
+fn suspended(wall: Duration, monotonic: Duration) -> Option<Duration> {
+    wall.checked_sub(monotonic)
+        .filter(|&gap| gap > SUSPEND_THRESHOLD)
+}
+
+/// Local time since the Unix epoch.
+pub fn local_now() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let offset = utils::utc_offset(utils::unix_time());
+    let offset_abs = Duration::from_secs(offset.unsigned_abs());
+    if offset >= 0 {
+        now + offset_abs
+    } else {
+        now.saturating_sub(offset_abs)
+    }
+}
+
+/// Time from `local` until the clock next shows a multiple of `interval`, e.g. the next full hour
+/// for an interval of an hour. A whole interval if it shows one right now.
+pub fn until_boundary(local: Duration, interval: Duration) -> Duration {
+    let interval = interval.as_millis().max(1);
+    let left = interval - local.as_millis() % interval;
+    Duration::from_millis(u64::try_from(left).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suspended() {
+        let secs = Duration::from_secs;
+        assert_eq!(suspended(secs(30), secs(30)), None);
+        // NTP nudging the clock.
+        assert_eq!(suspended(secs(32), secs(30)), None);
+        assert_eq!(suspended(secs(3630), secs(30)), Some(secs(3600)));
+        // The clock being set back.
+        assert_eq!(suspended(secs(0), secs(30)), None);
+    }
+
+    #[test]
+    fn test_until_boundary() {
+        let hour = Duration::from_hours(1);
+        // 17:40:30.
+        let local = Duration::from_secs(((19_000 * 24 + 17) * 60 + 40) * 60 + 30);
+        assert_eq!(
+            until_boundary(local, hour),
+            Duration::from_secs(19 * 60 + 30)
+        );
+        assert_eq!(
+            until_boundary(local, Duration::from_mins(15)),
+            Duration::from_secs(4 * 60 + 30)
+        );
+        assert_eq!(
+            until_boundary(local + Duration::from_secs(19 * 60 + 30), hour),
+            hour
+        );
+        assert_eq!(
+            until_boundary(local + Duration::from_millis(1_169_750), hour),
+            Duration::from_millis(250)
+        );
+    }
+}
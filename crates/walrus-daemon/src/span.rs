@@ -0,0 +1,175 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use image::ImageReader;
+use image::ImageResult;
+use walrus_core::config::FilterMethod;
+use walrus_core::config::MonitorInfo;
+
+use crate::magic;
+use crate::prescale;
+
+/// The part of a spanned image shown on one output.
+#[derive(Debug, PartialEq)]
+struct Slice {
+    output: String,
+    // x, y, width and height in image pixels.
+    crop: (u32, u32, u32, u32),
+    // Pixels on screen the crop is scaled to.
+    size: (u32, u32),
+}
+
+/// Cuts `source` into one image per output, so that together the outputs show the whole image
+/// the way they are laid out. Slices are stored in `dir` and reused until the source or the layout
+/// changes. Returns the output names with their slices, outputs without a name are left out.
+///
+/// Animated images aren't sliced, that would only keep their first frame. There are no slices for
+/// them, so they are set normally.
+pub fn slices(
+    source: &Path,
+    dimensions: (u32, u32),
+    outputs: &[MonitorInfo],
+    filter: &FilterMethod,
+    dir: &Path,
+) -> ImageResult<Vec<(String, PathBuf)>> {
+    if magic::is_animated(source) {
+        return Ok(Vec::new());
+    }
+
+    let layout = layout(dimensions, outputs);
+    let key = prescale::key(source, &format!("span {layout:?} {filter}"))?;
+    let paths = layout
+        .iter()
+        .map(|slice| dir.join(format!("{key}-{}.png", slice.output)))
+        .collect::<Vec<_>>();
+
+    let _rendering = prescale::rendering();
+    if paths.iter().all(|path| path.is_file()) {
+        for path in &paths {
+            prescale::touch(path)?;
+        }
+    } else {
+        let image = ImageReader::open(source)?.with_guessed_format()?.decode()?;
+        for (slice, path) in layout.iter().zip(&paths) {
+            let (x, y, width, height) = slice.crop;
+            let (screen_width, screen_height) = slice.size;
+            let scaled = image.crop_imm(x, y, width, height).resize_exact(
+                screen_width,
+                screen_height,
+                prescale::filter_type(filter),
+            );
+            prescale::save(&scaled, path)?;
+        }
+    }
+
+    Ok(layout
+        .into_iter()
+        .map(|slice| slice.output)
+        .zip(paths)
+        .collect())
+}
+
+// Scales the image to cover the bounding box of all outputs, centred like swww's crop, then cuts
+// out each output's part.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn layout((width, height): (u32, u32), outputs: &[MonitorInfo]) -> Vec<Slice> {
+    let outputs = outputs
+        .iter()
+        .filter(|o| o.name.is_some() && o.logical_size.0 > 0 && o.logical_size.1 > 0)
+        .collect::<Vec<_>>();
+    let (Some(left), Some(top), Some(right), Some(bottom)) = (
+        outputs.iter().map(|o| o.position.0).min(),
+        outputs.iter().map(|o| o.position.1).min(),
+        outputs
+            .iter()
+            .map(|o| o.position.0 + o.logical_size.0)
+            .max(),
+        outputs
+            .iter()
+            .map(|o| o.position.1 + o.logical_size.1)
+            .max(),
+    ) else {
+        return Vec::new();
+    };
+
+    // Image pixels per logical pixel.
+    let (total_width, total_height) = (f64::from(right - left), f64::from(bottom - top));
+    let scale = (f64::from(width) / total_width).min(f64::from(height) / total_height);
+    let offset_x = (f64::from(width) - total_width * scale) / 2.0;
+    let offset_y = (f64::from(height) - total_height * scale) / 2.0;
+
+    outputs
+        .into_iter()
+        .map(|output| {
+            let x = offset_x + f64::from(output.position.0 - left) * scale;
+            let y = offset_y + f64::from(output.position.1 - top) * scale;
+            let crop_width = (f64::from(output.logical_size.0) * scale).round() as u32;
+            let crop_height = (f64::from(output.logical_size.1) * scale).round() as u32;
+
+            let x = (x.round() as u32).min(width.saturating_sub(1));
+            let y = (y.round() as u32).min(height.saturating_sub(1));
+            Slice {
+                output: output.name.clone().unwrap_or_default(),
+                crop: (
+                    x,
+                    y,
+                    crop_width.clamp(1, width - x),
+                    crop_height.clamp(1, height - y),
+                ),
+                size: output.screen_size(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use walrus_core::config::Resolution;
+
+    use super::*;
+
+    fn output(name: &str, position: (i32, i32), resolution: (i32, i32), scale: i32) -> MonitorInfo {
+        MonitorInfo {
+            refresh_rate: 60.0,
+            resolution: Resolution {
+                width: resolution.0,
+                height: resolution.1,
+            },
+            id: 0,
+            name: Some(name.into()),
+            rotated: false,
+            position,
+            logical_size: (resolution.0 / scale, resolution.1 / scale),
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        // A 4K output at scale 2 next to a 1080p one, both 1920 logical pixels wide.
+        let outputs = [
+            output("DP-1", (0, 0), (3840, 2160), 2),
+            output("DP-2", (1920, 0), (1920, 1080), 1),
+        ];
+
+        // The image is wider than the layout, so it is cropped on both sides.
+        assert_eq!(
+            layout((8000, 2000), &outputs),
+            vec![
+                Slice {
+                    output: "DP-1".into(),
+                    crop: (444, 0, 3556, 2000),
+                    size: (3840, 2160),
+                },
+                Slice {
+                    output: "DP-2".into(),
+                    crop: (4000, 0, 3556, 2000),
+                    size: (1920, 1080),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::os::unix;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+const STATE_FILE: &str = "state.toml";
+// Holds a symlink to the wallpaper on each output, named after the output.
+const CURRENT_DIR: &str = "current";
+// Name of the symlink when swww is pointed at all outputs at once and their names are unknown.
+const ALL_OUTPUTS: &str = "all";
+
+/// What is on screen right now, kept in `$XDG_STATE_HOME/walrus` for other programs to read.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    pub wallpaper: PathBuf,
+    /// Seconds since the Unix epoch.
+    pub set_at: u64,
+    pub category: Option<String>,
+    pub transition: Option<String>,
+    /// The wallpaper on each output, which differs from `wallpaper` with `match_aspect` or span.
+    pub outputs: BTreeMap<String, PathBuf>,
+}
+
+impl State {
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(dir.join(STATE_FILE))?)?)
+    }
+
+    /// Writes the state file and points the symlinks at the current wallpapers. Every file is
+    /// replaced atomically, so readers never see a half-written state.
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let current = dir.join(CURRENT_DIR);
+        fs::create_dir_all(&current)?;
+
+        let tmp = dir.join(STATE_FILE).with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, dir.join(STATE_FILE))?;
+
+        let mut links = self
+            .outputs
+            .iter()
+            .map(|(output, wallpaper)| (output.as_str(), wallpaper))
+            .collect::<BTreeMap<_, _>>();
+        if links.is_empty() {
+            links.insert(ALL_OUTPUTS, &self.wallpaper);
+        }
+
+        for (output, wallpaper) in &links {
+            symlink(wallpaper, &current.join(output))?;
+        }
+        // Outputs that were unplugged since.
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !name.to_str().is_some_and(|name| links.contains_key(name)) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Creates the link under a temporary name and renames it over the old one.
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let tmp = link.with_extension("tmp");
+    let _ = fs::remove_file(&tmp);
+    unix::fs::symlink(target, &tmp)?;
+    fs::rename(&tmp, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let dir = env::temp_dir().join(format!("walrus-state-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut state = State {
+            wallpaper: PathBuf::from("/wallpapers/lake.png"),
+            set_at: 1_700_000_000,
+            category: Some("nature".into()),
+            transition: Some("wipe".into()),
+            outputs: BTreeMap::from([
+                ("DP-1".into(), PathBuf::from("/wallpapers/lake.png")),
+                ("DP-2".into(), PathBuf::from("/wallpapers/tower.png")),
+            ]),
+        };
+        state.save(&dir).unwrap();
+
+        let loaded = State::load(&dir).unwrap();
+        assert_eq!(loaded.wallpaper, state.wallpaper);
+        assert_eq!(loaded.category.as_deref(), Some("nature"));
+        assert_eq!(loaded.outputs, state.outputs);
+        assert_eq!(
+            fs::read_link(dir.join(CURRENT_DIR).join("DP-2")).unwrap(),
+            PathBuf::from("/wallpapers/tower.png")
+        );
+
+        // Links of outputs that are gone get removed.
+        state.outputs.remove("DP-2");
+        state.save(&dir).unwrap();
+        assert!(!dir.join(CURRENT_DIR).join("DP-2").exists());
+        assert!(dir.join(CURRENT_DIR).join("DP-1").is_symlink());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
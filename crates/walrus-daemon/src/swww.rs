@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::backend::BackendError;
+use crate::backend::Job;
+
+// How often to check whether a freshly started swww-daemon is ready.
+const READY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Asks swww-daemon what every output is showing. Outputs showing a colour instead of an image are
+/// left out. Fails if swww-daemon isn't running.
+pub fn query(
+    swww_path: &str,
+    timeout: Duration,
+) -> Result<BTreeMap<String, PathBuf>, BackendError> {
+    let output = Job::spawn(Command::new(swww_path).arg("query"))?.wait(timeout)?;
+    Ok(parse_query(&output))
+}
+
+/// Starts swww-daemon with `command` and waits until it answers queries, for at most `timeout`.
+pub fn start(command: &str, swww_path: &str, timeout: Duration) -> bool {
+    tracing::info!("Starting swww-daemon: {command}");
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .spawn();
+    match child {
+        // Reaped in the background whenever it exits.
+        Ok(mut child) => drop(thread::spawn(move || child.wait())),
+        Err(e) => {
+            tracing::error!("Error starting swww-daemon: {e}");
+            return false;
+        }
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        thread::sleep(READY_INTERVAL);
+        if query(swww_path, timeout).is_ok() {
+            return true;
+        }
+    }
+    tracing::error!("swww-daemon isn't ready after {timeout:?}");
+    false
+}
+
+// Lines look like `DP-1: 1920x1080, scale: 1, currently displaying: image: /path/to/image.png`,
+// newer versions of swww put the namespace in front, separated by another colon. It may be empty.
+fn parse_query(output: &str) -> BTreeMap<String, PathBuf> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (info, image) = line.split_once("image: ")?;
+            // Everything up to the resolution, which ends at the first comma.
+            let (names, _) = info.split_once(',')?;
+            let (names, _resolution) = names.rsplit_once(':')?;
+            let output = match names.rsplit_once(':') {
+                Some((_namespace, output)) => output,
+                None => names,
+            };
+            Some((output.trim().to_owned(), PathBuf::from(image.trim())))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let output = [
+            "DP-1: 2560x1440, scale: 1, currently displaying: image: /wallpapers/lake.png",
+            ": HDMI-A-1: 1920x1080, scale: 1, currently displaying: image: /wallpapers/a b.jpg",
+            "walrus: DP-2: 1080x1920, scale: 1, currently displaying: image: /wallpapers/tall.png",
+            "eDP-1: 1920x1200, scale: 1.25, currently displaying: color: 000000",
+        ]
+        .join("\n");
+
+        assert_eq!(
+            parse_query(&output),
+            BTreeMap::from([
+                ("DP-1".into(), PathBuf::from("/wallpapers/lake.png")),
+                ("HDMI-A-1".into(), PathBuf::from("/wallpapers/a b.jpg")),
+                ("DP-2".into(), PathBuf::from("/wallpapers/tall.png")),
+            ])
+        );
+    }
+}
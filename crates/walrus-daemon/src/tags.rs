@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::category;
+use crate::hash;
+
+/// Tags stored by content hash, so they survive wallpapers being moved or renamed. Identical copies
+/// of an image share its tags.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TagStore {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    images: BTreeMap<String, TaggedImage>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TaggedImage {
+    // Where the image was last tagged, to make the store readable. Images are matched by hash.
+    path: PathBuf,
+    tags: BTreeSet<String>,
+}
+
+impl TagStore {
+    /// Loads the store from `path`, starting out empty if the file doesn't exist yet.
+    pub fn load(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let mut store: Self = match fs::read_to_string(&path) {
+            Ok(raw) => toml::from_str(&raw)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        store.path = path;
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so a crash can't leave a truncated store behind.
+        let tmp = self.path.with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, wallpaper: &Path, tags: &[String]) -> io::Result<()> {
+        let image = self
+            .images
+            .entry(hash::content_hash(wallpaper)?)
+            .or_insert_with(|| TaggedImage {
+                path: wallpaper.to_owned(),
+                tags: BTreeSet::new(),
+            });
+        wallpaper.clone_into(&mut image.path);
+        image.tags.extend(tags.iter().cloned());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, wallpaper: &Path, tags: &[String]) -> io::Result<()> {
+        let hash = hash::content_hash(wallpaper)?;
+        if let Some(image) = self.images.get_mut(&hash) {
+            image.tags.retain(|tag| !tags.contains(tag));
+            if image.tags.is_empty() {
+                self.images.remove(&hash);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tags_of(&self, wallpaper: &Path) -> io::Result<Vec<String>> {
+        let hash = hash::content_hash(wallpaper)?;
+        Ok(self
+            .images
+            .get(&hash)
+            .map(|image| image.tags.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Every tag with the number of images it is on.
+    pub fn list(&self) -> BTreeMap<&str, usize> {
+        let mut tags = BTreeMap::new();
+        for tag in self.images.values().flat_map(|image| &image.tags) {
+            *tags.entry(tag.as_str()).or_default() += 1;
+        }
+        tags
+    }
+
+    /// Returns the wallpapers matching the query, given with their content hashes. Wallpapers
+    /// that aren't in the store have no tags.
+    pub fn query(&self, query: &Query, wallpapers: &[(PathBuf, String)]) -> Vec<PathBuf> {
+        let untagged = BTreeSet::new();
+        wallpapers
+            .iter()
+            .filter(|(_, hash)| query.matches(self.images.get(hash).map_or(&untagged, |i| &i.tags)))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Tags every categorised wallpaper with the name of its category. Returns how many
+    /// wallpapers were tagged.
+    pub fn import(&mut self, base: &Path) -> io::Result<usize> {
+        let mut count = 0;
+        for (name, _) in category::list(base)? {
+            for wallpaper in category::wallpapers(base, &name)? {
+                match self.add(&wallpaper, std::slice::from_ref(&name)) {
+                    Ok(()) => count += 1,
+                    Err(e) => tracing::warn!("Skipping {}: {e}", wallpaper.display()),
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// A boolean expression over tags, e.g. `tag:dark AND NOT (tag:busy OR tag:red)`.
+///
+/// `NOT` binds tighter than `AND`, which binds tighter than `OR`. Terms next to each other without
+/// an operator are joined with `AND`, and the `tag:` prefix is optional.
+#[derive(Debug, PartialEq)]
+pub enum Query {
+    Tag(String),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+impl Query {
+    pub fn matches(&self, tags: &BTreeSet<String>) -> bool {
+        match self {
+            Self::Tag(tag) => tags.contains(tag),
+            Self::Not(query) => !query.matches(tags),
+            Self::And(a, b) => a.matches(tags) && b.matches(tags),
+            Self::Or(a, b) => a.matches(tags) || b.matches(tags),
+        }
+    }
+
+    fn parse_or<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut query = Self::parse_and(tokens)?;
+        while tokens.next_if(|t| t.eq_ignore_ascii_case("or")).is_some() {
+            query = Self::Or(Box::new(query), Box::new(Self::parse_and(tokens)?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut query = Self::parse_not(tokens)?;
+        loop {
+            if tokens.next_if(|t| t.eq_ignore_ascii_case("and")).is_none()
+                && tokens
+                    .peek()
+                    .is_none_or(|t| *t == ")" || t.eq_ignore_ascii_case("or"))
+            {
+                return Ok(query);
+            }
+            query = Self::And(Box::new(query), Box::new(Self::parse_not(tokens)?));
+        }
+    }
+
+    fn parse_not<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        if tokens.next_if(|t| t.eq_ignore_ascii_case("not")).is_some() {
+            return Ok(Self::Not(Box::new(Self::parse_not(tokens)?)));
+        }
+
+        match tokens.next() {
+            Some("(") => {
+                let query = Self::parse_or(tokens)?;
+                match tokens.next() {
+                    Some(")") => Ok(query),
+                    _ => Err("Missing closing parenthesis".into()),
+                }
+            }
+            Some(")") => Err("Unexpected closing parenthesis".into()),
+            Some(token)
+                if token.eq_ignore_ascii_case("and") || token.eq_ignore_ascii_case("or") =>
+            {
+                Err(format!("Expected a tag, found {token}"))
+            }
+            Some(token) => {
+                let tag = token.strip_prefix("tag:").unwrap_or(token);
+                if tag.is_empty() {
+                    return Err("Empty tag".into());
+                }
+                Ok(Self::Tag(tag.into()))
+            }
+            None => Err("Expected a tag, found end of query".into()),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced.split_whitespace().peekable();
+
+        let query = Self::parse_or(&mut tokens)?;
+        match tokens.next() {
+            Some(token) => Err(format!("Unexpected {token} in query")),
+            None => Ok(query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[allow(clippy::unnecessary_box_returns)]
+    fn tag(name: &str) -> Box<Query> {
+        Box::new(Query::Tag(name.into()))
+    }
+
+    #[test]
+    fn test_query_parsing() {
+        assert_eq!(
+            "tag:dark AND NOT tag:busy".parse(),
+            Ok(Query::And(tag("dark"), Box::new(Query::Not(tag("busy")))))
+        );
+        // AND binds tighter than OR and terms are implicitly joined with AND.
+        assert_eq!(
+            "dark minimal or tag:red".parse(),
+            Ok(Query::Or(
+                Box::new(Query::And(tag("dark"), tag("minimal"))),
+                tag("red")
+            ))
+        );
+        assert_eq!(
+            "NOT (tag:a OR tag:b)".parse(),
+            Ok(Query::Not(Box::new(Query::Or(tag("a"), tag("b")))))
+        );
+
+        assert!("tag:a AND".parse::<Query>().is_err());
+        assert!("(tag:a".parse::<Query>().is_err());
+        assert!("tag:a)".parse::<Query>().is_err());
+        assert!("tag:".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn test_query_matching() {
+        let query: Query = "tag:dark AND NOT tag:busy".parse().unwrap();
+        let tags = |tags: &[&str]| tags.iter().map(|&t| t.to_owned()).collect::<BTreeSet<_>>();
+
+        assert!(query.matches(&tags(&["dark", "minimal"])));
+        assert!(!query.matches(&tags(&["dark", "busy"])));
+        assert!(!query.matches(&tags(&["light"])));
+    }
+
+    #[test]
+    fn test_query_untagged() {
+        let dir = env::temp_dir().join(format!("walrus-tags-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let wallpapers = ["lake.png", "city.png", "forest.png"].map(|name| dir.join(name));
+        for (i, wallpaper) in wallpapers.iter().enumerate() {
+            fs::write(wallpaper, i.to_string()).unwrap();
+        }
+
+        let mut store = TagStore::default();
+        store.add(&wallpapers[0], &["nsfw".into()]).unwrap();
+        store.add(&wallpapers[1], &["dark".into()]).unwrap();
+
+        let hashed = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| (path.clone(), hash::content_hash(path).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let query =
+            |query: &str, paths: &[PathBuf]| store.query(&query.parse().unwrap(), &hashed(paths));
+        assert_eq!(
+            query("not tag:nsfw", &wallpapers),
+            [wallpapers[1].clone(), wallpapers[2].clone()]
+        );
+        assert_eq!(query("tag:dark", &wallpapers), [wallpapers[1].clone()]);
+
+        // Moved images keep their tags, and so do identical copies.
+        let moved = dir.join("moved.png");
+        fs::rename(&wallpapers[1], &moved).unwrap();
+        let copy = dir.join("copy.png");
+        fs::copy(&moved, &copy).unwrap();
+        assert_eq!(
+            query("tag:dark", &[moved.clone(), copy.clone()]),
+            [moved, copy]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+use crate::palette::Color;
+use crate::palette::PALETTE_SIZE;
+
+// Always written, one colour per line, for scripts that don't need a template.
+const COLORS_FILE: &str = "colors";
+
+/// Writes the palette to `dir` and renders every template in `templates` into it, keeping the
+/// template's file name.
+///
+/// Templates use pywal's syntax: `{color0}` to `{color15}`, `{background}`, `{foreground}`,
+/// `{cursor}` and `{wallpaper}`. Colours can be written as `{color1.strip}` for `rrggbb` and
+/// `{color1.rgb}` for `r,g,b`. Literal braces are written as `{{` and `}}`.
+pub fn generate(
+    wallpaper: &Path,
+    palette: &[Color],
+    templates: Option<&Path>,
+    dir: &Path,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let colors = scheme(palette);
+    let hex = colors.iter().map(|c| c.hex()).collect::<Vec<_>>();
+    write(&dir.join(COLORS_FILE), &(hex.join("\n") + "\n"))?;
+
+    let Some(templates) = templates.filter(|t| t.is_dir()) else {
+        return Ok(());
+    };
+
+    let variables = variables(wallpaper, &colors);
+    for entry in fs::read_dir(templates)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let template = match fs::read_to_string(entry.path()) {
+            Ok(template) => template,
+            Err(e) => {
+                tracing::error!("Error reading template {}: {e}", entry.path().display());
+                continue;
+            }
+        };
+        write(&dir.join(entry.file_name()), &render(&template, &variables))?;
+    }
+    Ok(())
+}
+
+/// Runs the reload command in the background, so a slow command doesn't hold up walrus.
+pub fn reload(command: &str) {
+    let command = command.to_owned();
+    thread::spawn(
+        move || match Command::new("sh").arg("-c").arg(&command).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => tracing::error!("Theme reload command failed ({status}): {command}"),
+            Err(e) => tracing::error!("Error running theme reload command: {e}"),
+        },
+    );
+}
+
+// Stretches the palette to exactly PALETTE_SIZE colours, since templates expect all of them.
+fn scheme(palette: &[Color]) -> Vec<Color> {
+    if palette.is_empty() {
+        return vec![Color { r: 0, g: 0, b: 0 }; PALETTE_SIZE];
+    }
+    (0..PALETTE_SIZE)
+        .map(|i| palette[i * palette.len() / PALETTE_SIZE])
+        .collect()
+}
+
+fn variables(wallpaper: &Path, colors: &[Color]) -> HashMap<String, String> {
+    let mut named = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (format!("color{i}"), c))
+        .collect::<Vec<_>>();
+    // The darkest colour is the background and the lightest the foreground.
+    named.push(("background".into(), colors[0]));
+    named.push(("foreground".into(), colors[colors.len() - 1]));
+    named.push(("cursor".into(), colors[colors.len() - 1]));
+
+    let mut variables = HashMap::new();
+    for (name, c) in named {
+        variables.insert(format!("{name}.strip"), c.hex()[1..].to_owned());
+        variables.insert(format!("{name}.rgb"), format!("{},{},{}", c.r, c.g, c.b));
+        variables.insert(name, c.hex());
+    }
+    variables.insert("wallpaper".into(), wallpaper.display().to_string());
+    variables
+}
+
+// Unknown variables are left as they are, so stray braces in a template don't break it.
+fn render(template: &str, variables: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(i) = rest.find(['{', '}']) {
+        output.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            output.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let variable = rest
+            .strip_prefix('{')
+            .and_then(|r| r.split_once('}'))
+            .and_then(|(name, after)| Some((variables.get(name.trim())?, after)));
+        if let Some((value, after)) = variable {
+            output.push_str(value);
+            rest = after;
+        } else {
+            output.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+// Written to a temporary file first, so programs watching the file never read half of it.
+fn write(path: &Path, contents: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let palette = [
+            Color { r: 0, g: 0, b: 0 },
+            Color {
+                r: 255,
+                g: 128,
+                b: 0,
+            },
+        ];
+        let variables = variables(Path::new("/lake.png"), &scheme(&palette));
+
+        assert_eq!(
+            render(
+                "* {{ color: {color15}; }}\nbg={background.strip} fg={foreground.rgb} {nope} \
+                 {wallpaper}",
+                &variables
+            ),
+            "* { color: #ff8000; }\nbg=000000 fg=255,128,0 {nope} /lake.png"
+        );
+    }
+}
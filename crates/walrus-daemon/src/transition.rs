@@ -17,6 +17,7 @@ enum ImgArg {
     TransitionAngle(f32),     // For: Wipe, Wave
     TransitionPos(Pos),       // For: Grow, Outer
     TransitionWave(WaveSize), // For: Wave
+    Outputs(Vec<String>),
 }
 
 impl ImgArg {
@@ -46,11 +47,12 @@ impl ImgArg {
                 "--transition-wave".into(),
                 format!("{},{}", size.width, size.height),
             ],
+            Self::Outputs(outputs) => vec!["--outputs".into(), outputs.join(",")],
         }
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct TransitionArgBuilder {
     args: Vec<Vec<String>>,
 }
@@ -131,6 +133,12 @@ impl TransitionArgBuilder {
         self.args.push(arg);
         self
     }
+
+    pub fn with_outputs(mut self, outputs: Vec<String>) -> Self {
+        let arg = ImgArg::Outputs(outputs).to_args();
+        self.args.push(arg);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -183,7 +191,8 @@ mod tests {
                 width: 5,
                 height: 10,
             })
-            .with_pos(Pos { x: 10.0, y: 20.0 });
+            .with_pos(Pos { x: 10.0, y: 20.0 })
+            .with_outputs(vec!["DP-1".into(), "HDMI-A-1".into()]);
 
         (
             builder,
@@ -211,6 +220,8 @@ mod tests {
                 "5,10".into(),
                 "--transition-pos".into(),
                 "10,20".into(),
+                "--outputs".into(),
+                "DP-1,HDMI-A-1".into(),
             ],
         )
     }